@@ -1,26 +1,45 @@
 use mongodb::bson::oid::ObjectId;
 use mongodb::bson::DateTime;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "openapi")]
 use utoipa::ToSchema;
 
-use crate::serialize_option_oid_as_string;
-use crate::roles::RoleItems;
+use crate::roles::{Item, RoleItems, SchemaViolation, CRUD_VERBS};
+use crate::Result;
+use crate::{deserialize_option_oid_from_string, serialize_option_oid_as_string};
 
 pub const LOCAL_APP: &str = "local";
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, ToSchema)]
+fn default_crud_verbs() -> Vec<String> {
+    CRUD_VERBS.iter().map(|t| t.to_string()).collect()
+}
+
+fn default_crud_default() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct App {
     #[serde(
         rename(serialize = "id", deserialize = "_id"),
+        alias = "id",
+        default,
         skip_serializing_if = "Option::is_none",
-        serialize_with = "serialize_option_oid_as_string"
+        serialize_with = "serialize_option_oid_as_string",
+        deserialize_with = "deserialize_option_oid_from_string"
     )]
-    #[schema(value_type = String)]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub id: Option<ObjectId>,
     pub name: String,
     pub version: u64,
     pub default_role: RoleItems,
+    #[serde(default = "default_crud_verbs")]
+    pub crud_verbs: Vec<String>,
+    /// Value of every verb of a new CRUD item, `false` to deny by default.
+    #[serde(default = "default_crud_default")]
+    pub crud_default: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<DateTime>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -29,14 +48,18 @@ pub struct App {
 
 impl Default for App {
     fn default() -> Self {
-        Self {
+        let mut app = Self {
             id: None,
             name: LOCAL_APP.to_string(),
             version: 1,
-            default_role: RoleItems::local(),
+            default_role: RoleItems::default(),
+            crud_verbs: default_crud_verbs(),
+            crud_default: default_crud_default(),
             created_at: None,
             updated_at: None,
-        }
+        };
+        app.default_role = app.local_role();
+        app
     }
 }
 
@@ -44,7 +67,27 @@ impl App {
     pub fn id(&self) -> ObjectId {
         self.id.unwrap_or_default()
     }
+
+    /// CRUD item with the app verbs, set to the app `crud_default`.
+    pub fn crud_item<T: Into<String>>(&self, name: T) -> Item {
+        RoleItems::crud_verbs_with_default(name, &self.crud_verbs, self.crud_default)
+    }
+
+    /// `RoleItems::local` with the app verbs, set to the app `crud_default`.
+    pub fn local_role(&self) -> RoleItems {
+        RoleItems::new(vec![
+            self.crud_item("users"),
+            self.crud_item("roles"),
+            self.crud_item("apps"),
+        ])
+    }
+
+    /// `RoleItems::validate_crud_verbs` with the app verbs.
+    pub fn validate_crud(&self, items: &RoleItems) -> Result<Vec<SchemaViolation>> {
+        items.validate_crud_verbs(&self.crud_verbs)
+    }
 }
 
-#[derive(Serialize, ToSchema)]
-pub struct AppsVec(pub Vec<App>);
\ No newline at end of file
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct AppsVec(pub Vec<App>);
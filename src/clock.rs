@@ -0,0 +1,28 @@
+//! Time source of the cache TTLs, the reconcile interval and role expiry,
+//! replaceable so that time-dependent behavior can be tested without waiting.
+
+use std::fmt::Debug;
+use std::time::Instant;
+
+use mongodb::bson::DateTime;
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Current date, compared with the `expires_at` of items and values.
+    fn wall_time(&self) -> DateTime;
+}
+
+/// The wall clock, used unless the builder sets another one.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_time(&self) -> DateTime {
+        DateTime::now()
+    }
+}
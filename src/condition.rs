@@ -0,0 +1,211 @@
+//! Minimal expression language for conditional values.
+//!
+//! A condition is one or more comparisons joined with `&&` and `||`, `&&`
+//! binding tighter. There are no parentheses. Each comparison has the form
+//! `<operand> <op> <operand>` where `op` is one of `== != < <= > >=` and an
+//! operand is a context attribute (`$user.id`) or a literal: `'text'`,
+//! `"text"`, `42`, `1.5`, `true` or `false`.
+//!
+//! `$resource.owner == $user.id && $request.size <= 1024`
+//!
+//! A comparison referencing an attribute missing from the context is false.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::roles::DataValue;
+use crate::{AuthError, Result};
+
+/// Attributes conditions can reference, keyed by name without the `$`.
+pub type Context = HashMap<String, DataValue>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Attribute(String),
+    Literal(DataValue),
+    Op(Op),
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Operand {
+    Attribute(String),
+    Literal(DataValue),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Comparison {
+    left: Operand,
+    op: Op,
+    right: Operand,
+}
+
+fn invalid<T>(expr: &str, reason: &str) -> Result<T> {
+    Err(AuthError::InvalidCondition(format!("{}: {}", reason, expr)))
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = expr.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        match c {
+            t if t.is_whitespace() => i += 1,
+            '&' | '|' => {
+                if chars.get(i + 1) != Some(&c) {
+                    return invalid(expr, "expected && or ||");
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+                i += 2;
+            }
+            '=' | '!' | '<' | '>' => {
+                let eq = chars.get(i + 1) == Some(&'=');
+                let op = match (c, eq) {
+                    ('=', true) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', true) => Op::Le,
+                    ('>', true) => Op::Ge,
+                    ('<', false) => Op::Lt,
+                    ('>', false) => Op::Gt,
+                    _ => return invalid(expr, "unknown operator"),
+                };
+                tokens.push(Token::Op(op));
+                i += if eq { 2 } else { 1 };
+            }
+            '\'' | '"' => {
+                let end = match chars[i + 1..].iter().position(|t| *t == c) {
+                    Some(t) => i + 1 + t,
+                    None => return invalid(expr, "unterminated string"),
+                };
+                let text = chars[i + 1..end].iter().collect();
+                tokens.push(Token::Literal(DataValue::String(text)));
+                i = end + 1;
+            }
+            '$' => {
+                let len = chars[i + 1..]
+                    .iter()
+                    .take_while(|t| t.is_alphanumeric() || matches!(t, '_' | '-' | '.'))
+                    .count();
+                if len == 0 {
+                    return invalid(expr, "missing attribute name");
+                }
+                let name = chars[i + 1..i + 1 + len].iter().collect();
+                tokens.push(Token::Attribute(name));
+                i += 1 + len;
+            }
+            _ => {
+                let len = chars[i..]
+                    .iter()
+                    .take_while(|t| t.is_alphanumeric() || matches!(t, '_' | '-' | '.' | '+'))
+                    .count();
+                let word: String = chars[i..i + len].iter().collect();
+                let literal = match word.as_str() {
+                    "true" => DataValue::Boolean(true),
+                    "false" => DataValue::Boolean(false),
+                    t => match (t.parse::<i64>(), t.parse::<f64>()) {
+                        (Ok(t), _) => DataValue::Integer(t),
+                        (_, Ok(t)) => DataValue::Float(t),
+                        _ => return invalid(expr, "unexpected token"),
+                    },
+                };
+                tokens.push(Token::Literal(literal));
+                i += len;
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn operand(expr: &str, token: Option<&Token>) -> Result<Operand> {
+    match token {
+        Some(Token::Attribute(t)) => Ok(Operand::Attribute(t.to_owned())),
+        Some(Token::Literal(t)) => Ok(Operand::Literal(t.to_owned())),
+        _ => invalid(expr, "expected an attribute or a literal"),
+    }
+}
+
+/// Disjunction of conjunctions of comparisons.
+fn parse(expr: &str) -> Result<Vec<Vec<Comparison>>> {
+    let tokens = tokenize(expr)?;
+    let mut any = vec![];
+
+    for group in tokens.split(|t| *t == Token::Or) {
+        let mut all = vec![];
+
+        for cmp in group.split(|t| *t == Token::And) {
+            let op = match (cmp.len(), cmp.get(1)) {
+                (3, Some(Token::Op(t))) => *t,
+                _ => return invalid(expr, "expected <operand> <op> <operand>"),
+            };
+
+            all.push(Comparison {
+                left: operand(expr, cmp.first())?,
+                op,
+                right: operand(expr, cmp.get(2))?,
+            });
+        }
+
+        any.push(all);
+    }
+
+    Ok(any)
+}
+
+fn resolve<'c>(operand: &'c Operand, ctx: &'c Context) -> Option<&'c DataValue> {
+    match operand {
+        Operand::Attribute(t) => ctx.get(t),
+        Operand::Literal(t) => Some(t),
+    }
+}
+
+impl Comparison {
+    fn holds(&self, ctx: &Context) -> bool {
+        let (left, right) = match (resolve(&self.left, ctx), resolve(&self.right, ctx)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return false,
+        };
+
+        let ord = match (left, right) {
+            (DataValue::Integer(a), DataValue::Float(b)) => (*a as f64).partial_cmp(b),
+            (DataValue::Float(a), DataValue::Integer(b)) => a.partial_cmp(&(*b as f64)),
+            (a, b) => a.partial_cmp(b),
+        };
+
+        match self.op {
+            Op::Eq => ord == Some(Ordering::Equal),
+            Op::Ne => ord != Some(Ordering::Equal),
+            Op::Lt => ord == Some(Ordering::Less),
+            Op::Le => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+            Op::Gt => ord == Some(Ordering::Greater),
+            Op::Ge => matches!(ord, Some(Ordering::Greater | Ordering::Equal)),
+        }
+    }
+}
+
+/// Evaluate `expr` against `ctx`. Fails with `AuthError::InvalidCondition`
+/// when the expression doesn't follow the grammar.
+pub fn evaluate(expr: &str, ctx: &Context) -> Result<bool> {
+    let any = parse(expr)?;
+
+    Ok(any.iter().any(|all| all.iter().all(|t| t.holds(ctx))))
+}
+
+/// Condition granting when either `a` or `b` does.
+pub(crate) fn either(a: &str, b: &str) -> String {
+    format!("{} || {}", a, b)
+}
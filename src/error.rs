@@ -1,5 +1,11 @@
+use mongodb::error::{ErrorKind, RETRYABLE_WRITE_ERROR, TRANSIENT_TRANSACTION_ERROR};
 use thiserror::Error;
 
+/// Server error codes the driver itself retries reads on.
+const RETRYABLE_CODES: [i32; 13] = [
+    11600, 11602, 10107, 13435, 13436, 189, 91, 7, 6, 89, 9001, 134, 262,
+];
+
 #[derive(Debug, Error)]
 pub enum AuthError {
     #[error("MongoDB find API error. {0}")]
@@ -14,12 +20,36 @@ pub enum AuthError {
     MongoReadCursor(mongodb::error::Error),
     #[error("Could not watch a MongoDB watch stream. {0}")]
     MongoWatchChangeStream(mongodb::error::Error),
+    #[error("MongoDB find one and update API error. {0}")]
+    MongoFindOneAndUpdate(mongodb::error::Error),
+    #[error("MongoDB delete one API error. {0}")]
+    MongoDeleteOne(mongodb::error::Error),
+    #[error("MongoDB change streams are not supported, the server is not a replica set.")]
+    ChangeStreamsUnsupported,
+    #[error("The MongoDB change stream was closed.")]
+    ChangeStreamClosed,
+    #[error("Invalid role document. {0}")]
+    InvalidRoleDocument(mongodb::bson::de::Error),
+    #[error("Could not serialize role. {0}")]
+    RoleSerialization(mongodb::bson::ser::Error),
+    #[error("The role store is read-only.")]
+    ReadOnlyStore,
     #[error("Missing APP in database.")]
     MissingAppInDatabase,
+    #[error("Unknown app: {0}")]
+    UnknownApp(String),
+    #[error("Role inheritance cycle: {0}")]
+    RoleCycle(String),
+    #[error("Several roles are named {0}, the parent is ambiguous.")]
+    AmbiguousParent(String),
+    #[error("Missing roles in database: {}", .0.join(", "))]
+    MissingRoles(Vec<String>),
     #[error("Invalid Unicode string.")]
     InvalidUnicodeString,
     #[error("Invalid authorization path: {0}")]
     InvalidAuthPath(String),
+    #[error("Empty authorization path.")]
+    EmptyPath,
     #[error("Missing value.")]
     MissingValue,
     #[error("Missing parent path.")]
@@ -32,4 +62,94 @@ pub enum AuthError {
     MissingLastItem,
     #[error("Invalid data value type.")]
     InvalidDataValueType,
+    #[error("Change stream pipeline stage removes the resume token: {0}")]
+    InvalidChangeStreamPipeline(String),
+    #[error("Invalid patch. {0}")]
+    InvalidPatch(String),
+    #[error("Invalid condition. {0}")]
+    InvalidCondition(String),
+    #[error("Unsupported BSON type: {0}")]
+    UnsupportedBsonType(String),
+    #[error(
+        "Role exceeds the MongoDB document size limit: {}",
+        .name.as_deref().unwrap_or("unknown")
+    )]
+    RoleTooLarge { name: Option<String> },
+    #[error("Invalid roles snapshot. {0}")]
+    RolesSnapshot(serde_json::Error),
+    #[error("Value out of range: {0}")]
+    ValueOutOfRange(String),
+    #[error("Invalid static roles. {0}")]
+    InvalidStaticRoles(serde_json::Error),
+    #[cfg(feature = "redis")]
+    #[error("Could not connect to Redis. {0}")]
+    RedisConnect(redis::RedisError),
+    #[cfg(feature = "redis")]
+    #[error("Redis command error. {0}")]
+    Redis(redis::RedisError),
+}
+
+impl AuthError {
+    /// Whether the operation failed on a transient condition (network,
+    /// timeout, server selection, failover) and may succeed if retried.
+    /// Logical errors such as a missing app or an invalid path are not.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            AuthError::MongoFind(t)
+            | AuthError::MongoFindOne(t)
+            | AuthError::MongoParseUri(t)
+            | AuthError::MongoCreateClient(t)
+            | AuthError::MongoReadCursor(t)
+            | AuthError::MongoWatchChangeStream(t)
+            | AuthError::MongoFindOneAndUpdate(t)
+            | AuthError::MongoDeleteOne(t) => is_transient(t),
+            AuthError::ChangeStreamClosed => true,
+            #[cfg(feature = "redis")]
+            AuthError::RedisConnect(t) | AuthError::Redis(t) => {
+                t.is_io_error() || t.is_timeout() || t.is_connection_dropped()
+            }
+            AuthError::ChangeStreamsUnsupported
+            | AuthError::InvalidRoleDocument(_)
+            | AuthError::RoleSerialization(_)
+            | AuthError::ReadOnlyStore
+            | AuthError::MissingAppInDatabase
+            | AuthError::UnknownApp(_)
+            | AuthError::RoleCycle(_)
+            | AuthError::AmbiguousParent(_)
+            | AuthError::MissingRoles(_)
+            | AuthError::InvalidUnicodeString
+            | AuthError::InvalidAuthPath(_)
+            | AuthError::EmptyPath
+            | AuthError::MissingValue
+            | AuthError::MissingParentPath
+            | AuthError::MissingValueName
+            | AuthError::MissingValueExtension
+            | AuthError::MissingLastItem
+            | AuthError::InvalidDataValueType
+            | AuthError::InvalidChangeStreamPipeline(_)
+            | AuthError::InvalidPatch(_)
+            | AuthError::InvalidCondition(_)
+            | AuthError::UnsupportedBsonType(_)
+            | AuthError::InvalidStaticRoles(_)
+            | AuthError::RoleTooLarge { .. }
+            | AuthError::ValueOutOfRange(_)
+            | AuthError::RolesSnapshot(_) => false,
+        }
+    }
+}
+
+fn is_transient(err: &mongodb::error::Error) -> bool {
+    if err.contains_label(RETRYABLE_WRITE_ERROR) || err.contains_label(TRANSIENT_TRANSACTION_ERROR)
+    {
+        return true;
+    }
+
+    match err.kind.as_ref() {
+        ErrorKind::Io(_)
+        | ErrorKind::ConnectionPoolCleared { .. }
+        | ErrorKind::ServerSelection { .. }
+        | ErrorKind::DnsResolve { .. } => true,
+        ErrorKind::Command(t) => RETRYABLE_CODES.contains(&t.code),
+        _ => false,
+    }
 }
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::sync::Arc;
+
+use mongodb::bson::{Bson, Document};
+
+/// Adapter turning role documents stored with an older schema into the
+/// current `Role` shape before they are deserialized.
+#[derive(Clone)]
+pub enum LegacyFormat {
+    /// Item `values` stored as an object keyed by value name instead of an
+    /// array, e.g. `"values": { "create": { "boolean": true } }`.
+    ValuesMap,
+    /// Any other transformation of the raw role document.
+    Custom(Arc<dyn Fn(Document) -> Document + Send + Sync>),
+}
+
+impl fmt::Debug for LegacyFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LegacyFormat::ValuesMap => f.write_str("ValuesMap"),
+            LegacyFormat::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl LegacyFormat {
+    pub fn custom<F: Fn(Document) -> Document + Send + Sync + 'static>(src: F) -> Self {
+        LegacyFormat::Custom(Arc::new(src))
+    }
+
+    pub fn apply(&self, mut role: Document) -> Document {
+        match self {
+            LegacyFormat::ValuesMap => {
+                if let Some(Bson::Array(items)) = role.get_mut("items") {
+                    values_map_to_array(items);
+                }
+                role
+            }
+            LegacyFormat::Custom(f) => f(role),
+        }
+    }
+}
+
+fn values_map_to_array(items: &mut [Bson]) {
+    for item in items.iter_mut() {
+        let item = match item {
+            Bson::Document(t) => t,
+            _ => continue,
+        };
+
+        if let Some(Bson::Document(values)) = item.get("values") {
+            let values: Vec<Bson> = values
+                .iter()
+                .map(|(name, value)| {
+                    let mut value = match value {
+                        Bson::Document(t) => t.clone(),
+                        _ => Document::new(),
+                    };
+                    value.insert("name", name.to_owned());
+                    Bson::Document(value)
+                })
+                .collect();
+
+            item.insert("values", values);
+        }
+
+        if let Some(Bson::Array(t)) = item.get_mut("items") {
+            values_map_to_array(t);
+        }
+    }
+}
@@ -1,22 +1,58 @@
+use async_trait::async_trait;
 use futures::StreamExt;
-use futures::TryStreamExt;
 use haikunator::Haikunator;
 use log::error;
-use mongodb::bson::doc;
+use lru::LruCache;
 use mongodb::bson::oid::ObjectId;
-use mongodb::options::ClientOptions;
+use mongodb::bson::{doc, Bson, DateTime, Document};
+use mongodb::change_stream::event::ChangeStreamEvent;
+use mongodb::change_stream::ChangeStream;
+use mongodb::error::ErrorKind;
+use mongodb::options::{
+    ChangeStreamOptions, ClientOptions, Credential, FullDocumentBeforeChangeType, ReadPreference,
+    SelectionCriteria, Tls, TlsOptions,
+};
 use mongodb::{Client, Database};
 use roles::RoleItems;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::Interval;
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
 
 pub mod apps;
+pub mod clock;
+pub mod condition;
 mod error;
+pub mod legacy;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod multi;
+pub mod pattern;
+pub mod permissions;
+pub mod prelude;
+#[cfg(feature = "redis")]
+mod redis_cache;
 pub mod roles;
+mod static_roles;
+pub mod store;
+#[cfg(feature = "testing")]
+pub mod testing;
 
 use apps::App;
-use roles::Role;
+use clock::{Clock, SystemClock};
+use legacy::LegacyFormat;
+use multi::MultiAuth;
+use permissions::{PermissionSet, UserPermissions};
+use roles::{Crud, DataKind, DataValue, Role};
+use store::{LoadProgress, MongoStore, RoleStore, StaticStore};
 
 pub use error::AuthError;
 pub type Result<T> = std::result::Result<T, AuthError>;
@@ -24,6 +60,12 @@ pub type Result<T> = std::result::Result<T, AuthError>;
 const APPS: &str = "apps";
 const ROLES: &str = "roles";
 
+/// "The $changeStream stage is only supported on replica sets"
+const CHANGE_STREAMS_UNSUPPORTED_CODE: i32 = 40573;
+
+/// Sets of role names `AuthBuilder::cache_merged` keeps the permissions of.
+const MERGED_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(1024).unwrap();
+
 fn serialize_oid_as_string<S>(oid: &ObjectId, serializer: S) -> std::result::Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
@@ -44,55 +86,1050 @@ where
     }
 }
 
+/// Inverse of `serialize_oid_as_string`, also accepting an ObjectId as stored
+/// in MongoDB or as extended JSON `{"$oid": ...}`.
+fn deserialize_oid_from_string<'de, D>(deserializer: D) -> std::result::Result<ObjectId, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    oid_from_bson(Bson::deserialize(deserializer)?)
+}
+
+fn deserialize_option_oid_from_string<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<ObjectId>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<Bson>::deserialize(deserializer)?
+        .map(oid_from_bson)
+        .transpose()
+}
+
+fn oid_from_bson<E: serde::de::Error>(src: Bson) -> std::result::Result<ObjectId, E> {
+    match src {
+        Bson::ObjectId(t) => Ok(t),
+        Bson::String(t) => ObjectId::parse_str(t).map_err(E::custom),
+        t => Err(E::custom(format!(
+            "expected an ObjectId, found {:?}",
+            t.element_type()
+        ))),
+    }
+}
+
+/// How the background task started by `Auth::init` keeps the roles up to date.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RefreshStrategy {
+    /// Watch the roles collection, needs a replica set or a sharded cluster.
+    #[default]
+    ChangeStream,
+    /// Reload every role each `interval`, works on standalone servers.
+    Poll { interval: Duration },
+    /// Watch the roles collection, polling each `interval` instead when the
+    /// change stream can't be opened.
+    ChangeStreamOrPoll { interval: Duration },
+}
+
+/// What the roles cache is keyed by, i.e. what `Auth::permissions` and the
+/// other lookups expect as role names.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheKey {
+    /// The role name.
+    #[default]
+    Name,
+    /// The role id, as a hex string.
+    Id,
+    /// The app id and the role name joined by a slash, `<app id>/<name>`.
+    AppName,
+}
+
+impl CacheKey {
+    pub fn key(&self, role: &Role) -> String {
+        match self {
+            CacheKey::Name => role.name.to_owned(),
+            CacheKey::Id => role.id().to_hex(),
+            CacheKey::AppName => format!("{}/{}", role.app.to_hex(), role.name),
+        }
+    }
+
+    /// Database filter matching the role of `app` with the key, `None` if the
+    /// key is malformed or names a role of another app.
+    fn filter(&self, app: ObjectId, key: &str) -> Option<Document> {
+        match self {
+            CacheKey::Name => Some(doc! { "app": app, "name": key }),
+            CacheKey::Id => {
+                let id = ObjectId::parse_str(key).ok()?;
+                Some(doc! { "app": app, "_id": id })
+            }
+            CacheKey::AppName => {
+                let (app_id, name) = key.split_once('/')?;
+                let app_id = ObjectId::parse_str(app_id).ok()?;
+                // the key comes from the caller, it must not reach another app
+                (app_id == app).then(|| doc! { "app": app, "name": name })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CachedRole {
+    role: Role,
+    loaded_at: Instant,
+}
+
+/// Callback told why the change stream was given up on.
+#[derive(Clone)]
+struct ChangeStreamFailureHook(Arc<dyn Fn(&AuthError) + Send + Sync>);
+
+impl std::fmt::Debug for ChangeStreamFailureHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ChangeStreamFailureHook")
+    }
+}
+
+/// Callback told the number of roles each time the cache is replaced.
+#[derive(Clone)]
+struct RefreshHook(Arc<dyn Fn(usize) + Send + Sync>);
+
+impl std::fmt::Debug for RefreshHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RefreshHook")
+    }
+}
+
 #[derive(Clone, Debug)]
-pub struct Roles(Arc<RwLock<HashMap<String, Role>>>);
+pub struct Roles {
+    map: Arc<RwLock<HashMap<String, CachedRole>>>,
+    app: Arc<RwLock<Option<App>>>,
+    refreshed_at: Arc<RwLock<Option<DateTime>>>,
+    generation: Arc<AtomicU64>,
+    clock: Arc<dyn Clock>,
+    on_refresh: Option<RefreshHook>,
+    merged: Arc<std::sync::Mutex<MergedCache>>,
+}
 
 impl Default for Roles {
     fn default() -> Self {
-        Self(Arc::new(RwLock::new(HashMap::new())))
+        Self::with_clock(Arc::new(SystemClock))
     }
 }
 
 impl Roles {
-    async fn set(&self, src: HashMap<String, Role>) {
-        let mut lock = self.0.write().await;
+    fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            map: Arc::new(RwLock::new(HashMap::new())),
+            app: Arc::new(RwLock::new(None)),
+            refreshed_at: Arc::new(RwLock::new(None)),
+            generation: Arc::new(AtomicU64::new(0)),
+            clock,
+            on_refresh: None,
+            merged: Arc::new(std::sync::Mutex::new(MergedCache::default())),
+        }
+    }
+
+    async fn set(&self, app: App, src: HashMap<String, Role>) {
+        let now = self.clock.now();
+        let count = src.len();
+
+        let src = src
+            .into_iter()
+            .map(|(name, role)| {
+                let cached = CachedRole {
+                    role,
+                    loaded_at: now,
+                };
+                (name, cached)
+            })
+            .collect();
+
+        // every lock is taken before anything is written, so that a task
+        // aborted here leaves the previous cache whole
+        let mut app_lock = self.app.write().await;
+        let mut refreshed_at = self.refreshed_at.write().await;
+        let mut lock = self.map.write().await;
+
+        *app_lock = Some(app);
+        *refreshed_at = Some(self.clock.wall_time());
         *lock = src;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        drop((app_lock, refreshed_at, lock));
+
+        if let Some(t) = &self.on_refresh {
+            (t.0)(count);
+        }
     }
 
     async fn get<'r, T: Into<&'r str>>(&self, name: T) -> Option<Role> {
-        let lock = self.0.read().await;
-        lock.get(name.into()).cloned()
+        let lock = self.map.read().await;
+        lock.get(name.into()).map(|t| t.role.clone())
+    }
+
+    /// Like `get` but ignores entries loaded more than `ttl` ago.
+    async fn get_fresh(&self, name: &str, ttl: Duration) -> Option<Role> {
+        let now = self.clock.now();
+        let lock = self.map.read().await;
+        lock.get(name)
+            .filter(|t| now.saturating_duration_since(t.loaded_at) < ttl)
+            .map(|t| t.role.clone())
+    }
+
+    async fn insert(&self, key: String, role: Role) {
+        let mut lock = self.map.write().await;
+        let cached = CachedRole {
+            role,
+            loaded_at: self.clock.now(),
+        };
+        lock.insert(key, cached);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// `insert` unless the cached app is no longer `app`, e.g. after a
+    /// `reconfigure` while the role was fetched. False when left out.
+    async fn insert_for(&self, app: &App, key: String, role: Role) -> bool {
+        // held while inserting, `set` replaces the app and roles together
+        let lock = self.app.read().await;
+
+        if lock
+            .as_ref()
+            .is_none_or(|t| (t.id, &t.name) != (app.id, &app.name))
+        {
+            return false;
+        }
+
+        self.insert(key, role).await;
+
+        true
+    }
+
+    /// Evict the roles with the given id or name, or every role when both
+    /// are `None`.
+    async fn evict(&self, id: Option<ObjectId>, name: Option<&str>) {
+        let mut lock = self.map.write().await;
+        match (id, name) {
+            (None, None) => lock.clear(),
+            _ => lock
+                .retain(|key, t| (id.is_none() || t.role.id != id) && Some(key.as_str()) != name),
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Every cached role, sorted by name.
+    async fn all(&self) -> Vec<Role> {
+        let lock = self.map.read().await;
+        let mut roles: Vec<Role> = lock.values().map(|t| t.role.clone()).collect();
+        roles.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+        roles
+    }
+
+    async fn app(&self) -> Option<App> {
+        self.app.read().await.clone()
+    }
+
+    /// Number of times the cache has been modified.
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Merged permissions of the sorted `role_names` with their `valid_until`,
+    /// unless the cache changed since `generation` or an item expired since.
+    fn merged(
+        &self,
+        role_names: &[String],
+        generation: u64,
+    ) -> Option<(RoleItems, Option<DateTime>)> {
+        let mut lock = self.merged.lock().unwrap_or_else(|t| t.into_inner());
+
+        if lock.generation != generation {
+            lock.generation = generation;
+            lock.entries.clear();
+            return None;
+        }
+
+        let entry = lock.entries.get(role_names)?;
+
+        match entry.valid_until {
+            Some(t) if t <= self.clock.wall_time() => {
+                lock.entries.pop(role_names);
+                None
+            }
+            _ => Some((entry.items.clone(), entry.valid_until)),
+        }
+    }
+
+    /// Keep `items` until `valid_until`, the first expiry of the roles they're
+    /// merged from: one expiring may change the result, e.g. bring back a
+    /// value it had replaced, while nothing in `items` expires.
+    fn set_merged(
+        &self,
+        role_names: Vec<String>,
+        generation: u64,
+        items: RoleItems,
+        valid_until: Option<DateTime>,
+    ) {
+        let mut lock = self.merged.lock().unwrap_or_else(|t| t.into_inner());
+
+        if lock.generation != generation {
+            return;
+        }
+
+        let entry = MergedEntry { items, valid_until };
+        lock.entries.put(role_names, entry);
+    }
+}
+
+/// Merged permissions by sorted role names, see `AuthBuilder::cache_merged`.
+/// Entries are dropped as soon as the roles cache generation changes.
+#[derive(Debug)]
+struct MergedCache {
+    generation: u64,
+    entries: LruCache<Vec<String>, MergedEntry>,
+}
+
+impl Default for MergedCache {
+    fn default() -> Self {
+        Self {
+            generation: 0,
+            entries: LruCache::new(MERGED_CACHE_CAPACITY),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct MergedEntry {
+    items: RoleItems,
+    valid_until: Option<DateTime>,
+}
+
+/// Overview of the whole roles cache, e.g. for a `/debug/roles` endpoint.
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CacheExport {
+    pub app_name: String,
+    pub generation: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refreshed_at: Option<DateTime>,
+    pub roles: Vec<CachedRoleExport>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct CachedRoleExport {
+    pub name: String,
+    pub leaf_count: usize,
+}
+
+/// Settings `Auth::reconfigure` changes, `None` keeps the current one.
+#[derive(Clone, Debug, Default)]
+pub struct Reconfig {
+    pub app_name: Option<String>,
+}
+
+#[derive(Debug)]
+struct UserCacheEntry {
+    role_names: Vec<String>,
+    generation: u64,
+    items: Arc<RoleItems>,
+    /// First expiry of the merged roles.
+    valid_until: Option<DateTime>,
+}
+
+type UserCache = Arc<std::sync::Mutex<LruCache<String, UserCacheEntry>>>;
+
+#[derive(Debug)]
+struct PathSubscription {
+    role_names: Vec<String>,
+    path: PathBuf,
+    sender: watch::Sender<Option<DataValue>>,
+}
+
+/// Owner of the background watcher task, aborting it when dropped.
+#[derive(Debug, Default)]
+struct Watcher(std::sync::Mutex<Option<JoinHandle<()>>>);
+
+impl Watcher {
+    fn replace(&self, handle: Option<JoinHandle<()>>) {
+        let mut lock = self.0.lock().unwrap_or_else(|t| t.into_inner());
+
+        if let Some(t) = lock.take() {
+            t.abort();
+        }
+
+        *lock = handle;
+    }
+
+    fn is_running(&self) -> bool {
+        let lock = self.0.lock().unwrap_or_else(|t| t.into_inner());
+        lock.as_ref().is_some_and(|t| !t.is_finished())
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.replace(None);
     }
 }
 
 #[derive(Clone, Debug)]
 pub struct Auth {
     roles: Roles,
-    database: Database,
-    app_name: String,
+    store: Arc<dyn RoleStore>,
+    /// Roles database watched by the change stream, `None` for other stores.
+    roles_database: Option<Database>,
+    app_name: Arc<RwLock<String>>,
+    reconcile_interval: Option<Duration>,
+    refresh_strategy: RefreshStrategy,
+    last_reload: Arc<Mutex<Option<Instant>>>,
+    change_stream_pipeline: Vec<Document>,
+    change_stream_options: Option<ChangeStreamOptions>,
+    change_stream_retries: u32,
+    on_change_stream_failure: Option<ChangeStreamFailureHook>,
+    lazy_ttl: Option<Duration>,
+    cache_key: CacheKey,
+    case_insensitive_roles: bool,
+    fallback_role: Option<String>,
+    cache_merged: bool,
+    subscriptions: Arc<Mutex<Vec<Arc<PathSubscription>>>>,
+    watcher: Arc<Watcher>,
+    #[cfg(feature = "metrics")]
+    lookups: Arc<metrics::Histogram>,
+    user_cache: Option<UserCache>,
+    #[cfg(feature = "redis")]
+    redis: Option<redis_cache::RedisCache>,
 }
 
 impl Auth {
     pub async fn permissions(&self, role_names: Vec<String>) -> RoleItems {
-        let mut parent = RoleItems::default();
+        self.permissions_until(role_names).await.0
+    }
+
+    /// `permissions` with the first expiry of the merged roles.
+    async fn permissions_until(&self, role_names: Vec<String>) -> (RoleItems, Option<DateTime>) {
+        // lazy mode roles expire without a new generation
+        if !self.cache_merged || self.lazy_ttl.is_some() {
+            let (items, _, valid_until) = self.merge_checked(role_names).await;
+            return (items, valid_until);
+        }
+
+        let mut role_names: Vec<String> =
+            role_names.iter().map(|t| self.normalize_key(t)).collect();
+        role_names.sort();
+        role_names.dedup();
+
+        #[cfg(feature = "metrics")]
+        let started = Instant::now();
+
+        let generation = self.generation();
+
+        if let Some(t) = self.roles.merged(&role_names, generation) {
+            #[cfg(feature = "metrics")]
+            self.lookups.record(started.elapsed());
+
+            return t;
+        }
+
+        let (items, unknown_roles, valid_until) = self.merge_checked(role_names.clone()).await;
+
+        // names are the caller's, only known roles may take a place
+        if unknown_roles.is_empty() {
+            self.roles
+                .set_merged(role_names, generation, items.clone(), valid_until);
+        }
+
+        (items, valid_until)
+    }
+
+    /// `permissions` along with the requested role names that aren't cached,
+    /// e.g. to reject a typo instead of silently granting less. With the
+    /// builder `default_role`, that role is merged when none of them is.
+    pub async fn permissions_checked(&self, role_names: Vec<String>) -> (RoleItems, Vec<String>) {
+        let (items, unknown_roles, _) = self.merge_checked(role_names).await;
+        (items, unknown_roles)
+    }
+
+    /// `permissions_checked` with the first expiry of the merged roles.
+    async fn merge_checked(
+        &self,
+        role_names: Vec<String>,
+    ) -> (RoleItems, Vec<String>, Option<DateTime>) {
+        #[cfg(feature = "metrics")]
+        let started = Instant::now();
+
+        let mut roles = vec![];
+        let mut unknown_roles = vec![];
+
+        for name in role_names {
+            match self.role(name.as_str()).await {
+                Some(role) => roles.push(role),
+                None => unknown_roles.push(name),
+            }
+        }
+
+        if roles.is_empty() {
+            if let Some(t) = &self.fallback_role {
+                roles.extend(self.role(t).await);
+            }
+        }
+
+        let now = self.roles.clock.wall_time();
+        let items = roles::merge_roles_at(&roles, now);
+        let valid_until = next_expiry(roles.iter().map(|t| &t.items), now);
+
+        #[cfg(feature = "metrics")]
+        self.lookups.record(started.elapsed());
+
+        (items, unknown_roles, valid_until)
+    }
+
+    /// Timings of the permission lookups made so far.
+    #[cfg(feature = "metrics")]
+    pub fn cache_stats(&self) -> metrics::CacheStats {
+        self.lookups.stats()
+    }
+
+    /// Cached role by `CacheKey`. In lazy mode a missing or expired role is fetched from
+    /// the database, lookup errors are logged and reported as a missing role.
+    async fn role(&self, key: &str) -> Option<Role> {
+        let normalized = self.normalize_key(key);
+
+        let ttl = match self.lazy_ttl {
+            Some(t) => t,
+            None => return self.roles.get(normalized.as_str()).await,
+        };
+
+        if let Some(role) = self.roles.get_fresh(&normalized, ttl).await {
+            return Some(role);
+        }
+
+        let app = self.roles.app().await?;
+
+        let role = match self.find_role_with_parents(app.id(), key).await {
+            Ok(t) => t?,
+            Err(err) => {
+                error!("{}", err);
+                return None;
+            }
+        };
+
+        // a role of the previous app mustn't reach the new app cache
+        if !self.roles.insert_for(&app, normalized, role.clone()).await {
+            return None;
+        }
+
+        Some(role)
+    }
+
+    /// The role with the cache `key` from the store, with the items of its
+    /// parents fetched by name. Parents are copied into the role, a changed
+    /// parent shows in its children once they expire.
+    async fn find_role_with_parents(&self, app_id: ObjectId, key: &str) -> Result<Option<Role>> {
+        let role = match self
+            .store
+            .find_role(app_id, self.cache_key, key, self.case_insensitive_roles)
+            .await?
+        {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        let mut pending = role.parents.clone();
+        let mut roles = vec![role];
+
+        while let Some(name) = pending.pop() {
+            if roles
+                .iter()
+                .any(|t| self.normalize_key(&t.name) == self.normalize_key(&name))
+            {
+                continue;
+            }
+
+            let parent = self
+                .store
+                .find_role(app_id, CacheKey::Name, &name, self.case_insensitive_roles)
+                .await?
+                .ok_or_else(|| AuthError::MissingRoles(vec![name.to_string()]))?;
+
+            pending.extend(parent.parents.iter().cloned());
+            roles.push(parent);
+        }
+
+        let errors = roles::resolve_parents_each(&mut roles, self.case_insensitive_roles);
+
+        match errors.into_iter().next().flatten() {
+            Some(err) => Err(err),
+            None => Ok(Some(roles.swap_remove(0))),
+        }
+    }
+
+    /// `key` as found in the roles cache, lowercased with
+    /// `AuthBuilder::case_insensitive_roles`.
+    fn normalize_key(&self, key: &str) -> String {
+        match self.case_insensitive_roles {
+            true => key.to_lowercase(),
+            false => key.to_string(),
+        }
+    }
+
+    /// `roles` keyed for the roles cache, their parents resolved. A role
+    /// whose parents can't be resolved, e.g. a deleted parent or a cycle, is
+    /// logged and left out, the other roles are still cached.
+    fn role_map(&self, mut roles: Vec<Role>) -> HashMap<String, Role> {
+        let errors = roles::resolve_parents_each(&mut roles, self.case_insensitive_roles);
+
+        roles
+            .into_iter()
+            .zip(errors)
+            .filter_map(|(t, err)| match err {
+                Some(err) => {
+                    error!("Role {} left out of the cache. {}", t.name, err);
+                    None
+                }
+                None => Some((self.normalize_key(&self.cache_key.key(&t)), t)),
+            })
+            .collect()
+    }
+
+    /// Load a user's permissions from the role names stored with the user,
+    /// whatever the user schema: the app `default_role` with the known roles
+    /// merged on top. Unknown role names are reported, not fatal.
+    pub async fn permissions_for_user(
+        &self,
+        user_role_names: &[String],
+    ) -> Result<UserPermissions> {
+        let (app, roles, unknown_roles) = self.user_roles(user_role_names).await?;
+
+        Ok(UserPermissions {
+            items: merge_user_roles(app, &roles, self.roles.clock.wall_time()),
+            unknown_roles,
+        })
+    }
+
+    /// Loaded app with the known and unknown roles among `user_role_names`.
+    async fn user_roles(
+        &self,
+        user_role_names: &[String],
+    ) -> Result<(App, Vec<Role>, Vec<String>)> {
+        let app = self
+            .roles
+            .app()
+            .await
+            .ok_or(AuthError::MissingAppInDatabase)?;
+
+        let mut roles = vec![];
+        let mut unknown_roles = vec![];
+
+        for name in user_role_names {
+            match self.role(name).await {
+                Some(role) => roles.push(role),
+                None => unknown_roles.push(name.to_owned()),
+            }
+        }
+
+        Ok((app, roles, unknown_roles))
+    }
+
+    /// `permissions_for_user` items, looked up in Redis first when the builder
+    /// sets `redis_url`, with the first expiry of the roles they're merged from.
+    async fn user_items(
+        &self,
+        user_role_names: &[String],
+    ) -> Result<(RoleItems, Option<DateTime>)> {
+        let now = self.roles.clock.wall_time();
+        let (app, roles, _) = self.user_roles(user_role_names).await?;
+
+        let valid_until = next_expiry(
+            roles.iter().map(|t| &t.items).chain([&app.default_role]),
+            now,
+        );
+
+        #[cfg(feature = "redis")]
+        if let Some(redis) = &self.redis {
+            let key = redis_cache::RedisCache::key(&app.name, &app, &roles);
+
+            if let Some(items) = redis.get(&key).await {
+                return Ok((items, valid_until));
+            }
+
+            let items = merge_user_roles(app, &roles, now);
+            let valid_for = valid_until.map(|t| {
+                Duration::from_millis((t.timestamp_millis() - now.timestamp_millis()) as u64)
+            });
+            redis.set(&key, &items, valid_for).await;
+
+            return Ok((items, valid_until));
+        }
+
+        Ok((merge_user_roles(app, &roles, now), valid_until))
+    }
+
+    /// `permissions_for_user` items, cached per `user_key` when the builder
+    /// enables `user_permission_cache`. An entry is reused while the role names
+    /// are the same, the roles cache wasn't refreshed since and none of the
+    /// roles items expired. Not used in lazy mode. On a miss the `redis`
+    /// feature consults the shared Redis cache before merging locally.
+    pub async fn permissions_for_user_cached(
+        &self,
+        user_key: &str,
+        role_names: &[String],
+    ) -> Result<Arc<RoleItems>> {
+        let generation = self.generation();
+        // lazy mode roles expire without a new generation
+        let user_cache = self.user_cache.as_ref().filter(|_| self.lazy_ttl.is_none());
+
+        if let Some(cache) = user_cache {
+            let mut lock = cache.lock().unwrap_or_else(|t| t.into_inner());
+
+            if let Some(t) = lock.get(user_key) {
+                if t.generation == generation
+                    && t.role_names == role_names
+                    && t.valid_until
+                        .is_none_or(|t| t > self.roles.clock.wall_time())
+                {
+                    return Ok(t.items.clone());
+                }
+            }
+        }
+
+        let (items, valid_until) = self.user_items(role_names).await?;
+        let items = Arc::new(items);
+
+        if let Some(cache) = user_cache {
+            let entry = UserCacheEntry {
+                role_names: role_names.to_vec(),
+                generation,
+                items: items.clone(),
+                valid_until,
+            };
+
+            let mut lock = cache.lock().unwrap_or_else(|t| t.into_inner());
+            lock.put(user_key.to_string(), entry);
+        }
+
+        Ok(items)
+    }
+
+    /// Names of the cached roles tagged with `tag`, sorted. In lazy mode only
+    /// the roles loaded so far are considered.
+    pub async fn roles_with_tag(&self, tag: &str) -> Vec<String> {
+        self.roles
+            .all()
+            .await
+            .into_iter()
+            .filter(|t| t.tags.iter().any(|t| t == tag))
+            .map(|t| t.name)
+            .collect()
+    }
+
+    /// Merge every cached role tagged with `tag`, in name order.
+    pub async fn permissions_by_tag(&self, tag: &str) -> RoleItems {
+        let roles = self.roles.all().await;
+
+        roles::merge_roles_at(
+            roles.iter().filter(|t| t.tags.iter().any(|t| t == tag)),
+            self.roles.clock.wall_time(),
+        )
+    }
+
+    /// Merge the given roles once and keep the result for repeated lookups.
+    /// The returned set remembers the cache generation it was built against
+    /// and the first expiry of the roles, see `PermissionSet::is_stale`.
+    pub async fn permission_set(&self, role_names: &[&str]) -> Result<PermissionSet> {
+        let generation = self.generation();
+        let (items, valid_until) = self
+            .permissions_until(role_names.iter().map(|t| t.to_string()).collect())
+            .await;
+
+        Ok(PermissionSet::new(items, generation, valid_until))
+    }
+
+    /// Whether the merged roles resolve `path` to `Boolean(true)`. A
+    /// `Boolean(false)` is `Ok(false)`, while a path missing from the roles
+    /// fails, e.g. with `AuthError::MissingValue`, so that a denial can be
+    /// told apart from a misconfiguration.
+    pub async fn is_allowed<P: ?Sized + AsRef<Path>>(
+        &self,
+        role_names: Vec<String>,
+        path: &P,
+    ) -> Result<bool> {
+        allowed(&self.permissions(role_names).await, path)
+    }
+
+    /// `is_allowed` for each of `paths`, merging the roles once. Results are
+    /// keyed by path, a path that fails doesn't affect the others.
+    pub async fn check_many<P: AsRef<Path>>(
+        &self,
+        role_names: Vec<String>,
+        paths: &[P],
+    ) -> HashMap<String, Result<bool>> {
+        let items = self.permissions(role_names).await;
+
+        paths
+            .iter()
+            .map(|t| {
+                let path = t.as_ref();
+                (path.display().to_string(), allowed(&items, path))
+            })
+            .collect()
+    }
+
+    /// Whether the roles grant `verb` on `resource`, i.e. whether
+    /// `/{resource}/{verb}.boolean` is `true`. Nested resources are slash
+    /// joined, `billing/invoices`. A missing item or verb is not granted, a
+    /// verb that isn't a boolean fails with `AuthError::InvalidDataValueType`.
+    pub async fn can_do(&self, role_names: &[&str], resource: &str, verb: Crud) -> Result<bool> {
+        let items = self
+            .permissions(role_names.iter().map(|t| t.to_string()).collect())
+            .await;
+
+        let pointer: String = resource
+            .trim_matches('/')
+            .split('/')
+            .map(|t| format!("/{}", t.replace('~', "~0")))
+            .collect();
+
+        match items.find_value_pointer(&format!("{}/{}", pointer, verb), DataKind::Boolean) {
+            Ok(t) => Ok(t == DataValue::Boolean(true)),
+            Err(AuthError::InvalidDataValueType) => Err(AuthError::InvalidDataValueType),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Effective permissions of the roles as a sorted, indented tree, e.g.
+    /// to answer "what can this user do" when debugging.
+    pub async fn dump(&self, role_names: &[&str]) -> Result<String> {
+        let mut items = self
+            .permissions(role_names.iter().map(|t| t.to_string()).collect())
+            .await;
+
+        items.sort();
+
+        Ok(items.to_string())
+    }
+
+    /// Merge the roles into a new, unsaved `Role` of the current app, e.g. to
+    /// save a combination of roles as a role of its own.
+    pub async fn effective_role<T: Into<String>>(
+        &self,
+        name: T,
+        role_names: &[&str],
+    ) -> Result<Role> {
+        let app = self
+            .roles
+            .app()
+            .await
+            .ok_or(AuthError::MissingAppInDatabase)?;
+
+        let items = self
+            .permissions(role_names.iter().map(|t| t.to_string()).collect())
+            .await;
+
+        let now = DateTime::now();
+
+        Ok(Role {
+            id: None,
+            app: app.id(),
+            name: name.into(),
+            items,
+            created_at: Some(now),
+            updated_at: Some(now),
+            ..Role::default()
+        })
+    }
+
+    /// Watch the value `path` resolves to for the given roles. A new value
+    /// is only sent when a cache refresh changes it, `None` when the path no
+    /// longer resolves. Dropping the receiver ends the subscription.
+    pub async fn subscribe_path<P: AsRef<Path>>(
+        &self,
+        role_names: Vec<String>,
+        path: P,
+    ) -> watch::Receiver<Option<DataValue>> {
+        let path = path.as_ref().to_path_buf();
+        let value = self
+            .permissions(role_names.clone())
+            .await
+            .find_value(&path)
+            .ok();
+
+        let (sender, receiver) = watch::channel(value);
+
+        let subscription = PathSubscription {
+            role_names,
+            path,
+            sender,
+        };
+
+        self.subscriptions.lock().await.push(Arc::new(subscription));
+
+        receiver
+    }
+
+    async fn notify_subscribers(&self) {
+        let subscriptions = {
+            let mut lock = self.subscriptions.lock().await;
+            lock.retain(|t| !t.sender.is_closed());
+            lock.clone()
+        };
+
+        for subscription in subscriptions {
+            let value = self
+                .permissions(subscription.role_names.clone())
+                .await
+                .find_value(&subscription.path)
+                .ok();
+
+            subscription.sender.send_if_modified(|current| {
+                if *current == value {
+                    return false;
+                }
+                *current = value;
+                true
+            });
+        }
+    }
+
+    /// Every cached role as pretty JSON, sorted by name then id so that
+    /// snapshots diff well. See `import_roles`.
+    pub async fn export_roles(&self) -> Result<String> {
+        serde_json::to_string_pretty(&self.roles.all().await).map_err(AuthError::RolesSnapshot)
+    }
+
+    /// Store `role` in the loaded app, replacing the role with the same id, or
+    /// the same name when it has none, and return its id. The cache sees it
+    /// once the watcher reloads, or after `refresh`. Fails with
+    /// `AuthError::ReadOnlyStore` for static roles.
+    pub async fn upsert_role(&self, role: &Role) -> Result<ObjectId> {
+        let app = self
+            .roles
+            .app()
+            .await
+            .ok_or(AuthError::MissingAppInDatabase)?;
+
+        self.store.upsert_role(app.id(), role).await
+    }
+
+    /// Delete the role of the loaded app named `name`, `false` when there is
+    /// none. Like `upsert_role`, the cache sees it once reloaded.
+    pub async fn delete_role(&self, name: &str) -> Result<bool> {
+        let app = self
+            .roles
+            .app()
+            .await
+            .ok_or(AuthError::MissingAppInDatabase)?;
+
+        self.store.delete_role(app.id(), name).await
+    }
+
+    /// Replace the cached roles with the `export_roles` JSON `src`, without
+    /// touching the store, e.g. to run against a fixed snapshot in tests. The
+    /// roles belong to the loaded app, a default one named after the app when
+    /// none was loaded. The watcher reloads the store on its next event.
+    pub async fn import_roles(&self, src: &str) -> Result<()> {
+        let roles: Vec<Role> = serde_json::from_str(src).map_err(AuthError::RolesSnapshot)?;
+
+        let app = match self.roles.app().await {
+            Some(t) => t,
+            None => App {
+                name: self.app_name.read().await.to_owned(),
+                ..App::default()
+            },
+        };
+
+        let map = self.role_map(roles);
+
+        self.roles.set(app, map).await;
+        self.notify_subscribers().await;
+
+        Ok(())
+    }
+
+    /// Snapshot of the roles cache: app, generation, last full refresh and
+    /// per-role leaf counts, roles sorted by name.
+    pub async fn export_cache(&self) -> CacheExport {
+        let generation = self.generation();
+
+        let roles = self
+            .roles
+            .all()
+            .await
+            .into_iter()
+            .map(|t| CachedRoleExport {
+                leaf_count: t.items.leaf_count(),
+                name: t.name,
+            })
+            .collect();
+
+        CacheExport {
+            app_name: self.app_name.read().await.to_owned(),
+            generation,
+            refreshed_at: *self.roles.refreshed_at.read().await,
+            roles,
+        }
+    }
+
+    /// Apply `src` and reload the roles, reusing the MongoDB client. The
+    /// running watcher follows the new app from its next event. Lookups see
+    /// the previous roles until the new ones are loaded at once. If the reload
+    /// fails the previous settings are restored and the cache is left as is.
+    /// Static roles are not reloaded.
+    pub async fn reconfigure(&self, src: Reconfig) -> Result<()> {
+        let previous = match src.app_name {
+            Some(t) => std::mem::replace(&mut *self.app_name.write().await, t),
+            None => return Ok(()),
+        };
 
-        for name in role_names {
-            if let Some(role) = self.roles.get(name.as_str()).await {
-                role.items.add(&mut parent);
-            }
+        if let Err(err) = self.update_roles().await {
+            *self.app_name.write().await = previous;
+            return Err(err);
         }
 
-        parent
+        Ok(())
+    }
+
+    /// Current roles cache generation. It changes every time the cache is reloaded.
+    pub fn generation(&self) -> u64 {
+        self.roles.generation()
     }
 }
 
-#[derive(Debug)]
 pub struct MongoDB {
     pub uri: String,
     pub db_name: String,
     pub client_name: String,
 }
 
+/// The URI password is redacted.
+impl std::fmt::Debug for MongoDB {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MongoDB")
+            .field("uri", &redact_uri(&self.uri))
+            .field("db_name", &self.db_name)
+            .field("client_name", &self.client_name)
+            .finish()
+    }
+}
+
+/// `uri` with the password of its `user:password@` part, if any, redacted.
+fn redact_uri(uri: &str) -> String {
+    let (scheme, rest) = match uri.split_once("://") {
+        Some(t) => t,
+        None => return uri.to_string(),
+    };
+
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+
+    match rest[..authority_end].rsplit_once('@') {
+        Some((user_info, _)) if user_info.contains(':') => {
+            let user = user_info.split(':').next().unwrap_or_default();
+            let host = &rest[user_info.len()..];
+            format!("{}://{}:REDACTED{}", scheme, user, host)
+        }
+        _ => uri.to_string(),
+    }
+}
+
 impl Default for MongoDB {
     fn default() -> Self {
         Self {
@@ -106,7 +1143,50 @@ impl Default for MongoDB {
 #[derive(Debug)]
 pub struct AuthBuilder {
     mongodb: MongoDB,
+    client: Option<Client>,
+    server_selection_timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    verify_app: bool,
+    credential: Option<Credential>,
+    tls: Option<TlsOptions>,
+    apps_db_name: Option<String>,
+    roles_db_name: Option<String>,
     app_name: String,
+    reconcile_interval: Option<Duration>,
+    refresh_strategy: RefreshStrategy,
+    change_stream_pipeline: Vec<Document>,
+    change_stream_options: Option<ChangeStreamOptions>,
+    change_stream_read_preference: Option<ReadPreference>,
+    change_stream_pre_image: bool,
+    change_stream_retries: u32,
+    on_change_stream_failure: Option<ChangeStreamFailureHook>,
+    lazy_ttl: Option<Duration>,
+    legacy_format: Option<LegacyFormat>,
+    load_progress: Option<LoadProgress>,
+    on_refresh: Option<RefreshHook>,
+    cache_key: CacheKey,
+    case_insensitive_roles: bool,
+    fallback_role: Option<String>,
+    cache_merged: bool,
+    user_permission_cache: Option<NonZeroUsize>,
+    static_roles: Option<String>,
+    store: Option<Arc<dyn RoleStore>>,
+    clock: Arc<dyn Clock>,
+    #[cfg(feature = "redis")]
+    redis_url: Option<RedisUrl>,
+    #[cfg(feature = "redis")]
+    redis_ttl: Duration,
+}
+
+/// Redis URL, its password redacted from `Debug`.
+#[cfg(feature = "redis")]
+struct RedisUrl(String);
+
+#[cfg(feature = "redis")]
+impl std::fmt::Debug for RedisUrl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&redact_uri(&self.0))
+    }
 }
 
 impl AuthBuilder {
@@ -130,22 +1210,428 @@ impl AuthBuilder {
         self
     }
 
+    /// Give up on reaching a suitable server after `src` instead of the driver
+    /// default of 30s, e.g. to fail fast at startup. Not applied to a
+    /// `with_client` client.
+    pub fn server_selection_timeout(&mut self, src: Duration) -> &mut Self {
+        self.server_selection_timeout = Some(src);
+        self
+    }
+
+    /// Give up on opening a connection after `src` instead of the driver
+    /// default of 10s. Not applied to a `with_client` client.
+    pub fn connect_timeout(&mut self, src: Duration) -> &mut Self {
+        self.connect_timeout = Some(src);
+        self
+    }
+
+    /// Look the app up in `build`, failing with
+    /// `AuthError::MissingAppInDatabase` when it doesn't exist instead of on
+    /// the first reload. Off by default, `build` then doesn't touch MongoDB.
+    pub fn verify_app(&mut self, src: bool) -> &mut Self {
+        self.verify_app = src;
+        self
+    }
+
+    /// Authenticate as `username` with `password` instead of the URI user and
+    /// password, keeping the URI `authSource` and `authMechanism`, if any.
+    /// Not applied to a `with_client` client.
+    pub fn mongodb_credential<U, P>(&mut self, username: U, password: P) -> &mut Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.credential = Some(
+            Credential::builder()
+                .username(username.into())
+                .password(password.into())
+                .build(),
+        );
+        self
+    }
+
+    /// Connect over TLS, checking the server certificate against the CA file
+    /// `ca_file` or the bundled Mozilla roots when `None`. Replaces the URI
+    /// TLS options. Not applied to a `with_client` client.
+    pub fn mongodb_tls(&mut self, ca_file: Option<PathBuf>) -> &mut Self {
+        self.tls = Some(TlsOptions::builder().ca_file_path(ca_file).build());
+        self
+    }
+
+    /// Share the connection pool of an existing `client` instead of creating
+    /// one from `mongodb_uri`, with `db_name` as the `mongodb_db_name`.
+    pub fn with_client<T: Into<String>>(&mut self, client: Client, db_name: T) -> &mut Self {
+        self.client = Some(client);
+        self.mongodb.db_name = db_name.into();
+        self
+    }
+
+    /// Database holding the apps collection. Defaults to `mongodb_db_name`.
+    pub fn apps_db_name<T: Into<String>>(&mut self, src: T) -> &mut Self {
+        self.apps_db_name = Some(src.into());
+        self
+    }
+
+    /// Database holding the roles collection. Defaults to `mongodb_db_name`.
+    pub fn roles_db_name<T: Into<String>>(&mut self, src: T) -> &mut Self {
+        self.roles_db_name = Some(src.into());
+        self
+    }
+
+    /// Periodically reload every role, in addition to the change stream, so
+    /// that missed events can't leave the cache out of date. A tick is skipped
+    /// when a reload already completed within the last interval. Off by default.
+    pub fn reconcile_interval(&mut self, src: Duration) -> &mut Self {
+        self.reconcile_interval = Some(src);
+        self
+    }
+
+    /// Poll the roles instead of watching them, e.g. on standalone servers in
+    /// development. Defaults to `RefreshStrategy::ChangeStream`.
+    pub fn refresh_strategy(&mut self, src: RefreshStrategy) -> &mut Self {
+        self.refresh_strategy = src;
+        self
+    }
+
+    /// Replace the default `watch(vec![], None)` subscription on the roles
+    /// collection. Every event the stream yields still triggers a full reload,
+    /// so the caller becomes responsible for not filtering out role changes.
+    /// Stages removing the `_id` resume token are rejected by `build`.
+    pub fn change_stream(
+        &mut self,
+        pipeline: Vec<Document>,
+        options: ChangeStreamOptions,
+    ) -> &mut Self {
+        self.change_stream_pipeline = pipeline;
+        self.change_stream_options = Some(options);
+        self
+    }
+
+    /// Open the change stream on servers matching `src` instead of the
+    /// primary, the driver default. Events from a secondary are only seen
+    /// once replicated there, so cache updates lag by the replication delay.
+    pub fn change_stream_read_preference(&mut self, src: ReadPreference) -> &mut Self {
+        self.change_stream_read_preference = Some(src);
+        self
+    }
+
+    /// Ask the change stream for the document as it was before each change,
+    /// so that deletes and replaces evict the role by its previous name in lazy
+    /// mode. Pre-images need MongoDB 6.0+ with `changeStreamPreAndPostImages`
+    /// enabled on the roles collection. They're requested "when available", so
+    /// without server support eviction falls back to the document `_id`.
+    pub fn change_stream_pre_image(&mut self, src: bool) -> &mut Self {
+        self.change_stream_pre_image = src;
+        self
+    }
+
+    /// Try `src` times, 5 by default, to reopen the change stream when it
+    /// fails or closes, waiting 1s, then twice as long each time up to 30s.
+    /// Roles are reloaded once it's open again, the events in between are lost.
+    /// When every attempt fails, `ChangeStreamOrPoll` falls back to polling and
+    /// the other strategies only keep the reconcile interval, if any.
+    pub fn change_stream_retries(&mut self, src: u32) -> &mut Self {
+        self.change_stream_retries = src;
+        self
+    }
+
+    /// Call `src` with the last error when the watcher gives up on the change
+    /// stream, see `change_stream_retries`.
+    pub fn on_change_stream_failure<F>(&mut self, src: F) -> &mut Self
+    where
+        F: Fn(&AuthError) + Send + Sync + 'static,
+    {
+        self.on_change_stream_failure = Some(ChangeStreamFailureHook(Arc::new(src)));
+        self
+    }
+
+    /// Don't preload every role of the app. Roles are fetched from the
+    /// database the first time they are requested and kept for `ttl`, change
+    /// stream events evict the affected role instead of reloading all of them.
+    /// Suited to large role catalogs where each request needs only a few roles.
+    pub fn lazy(&mut self, ttl: Duration) -> &mut Self {
+        self.lazy_ttl = Some(ttl);
+        self
+    }
+
+    /// Key the roles cache by something else than the role name, e.g. when
+    /// role names are not unique. Role lookups then expect keys of that kind.
+    pub fn cache_key(&mut self, src: CacheKey) -> &mut Self {
+        self.cache_key = src;
+        self
+    }
+
+    /// Look roles up by name regardless of case, `Admin` finding the role
+    /// named `admin`. Off by default, when two roles differ only by case one of
+    /// them is unreachable. In lazy mode the store is asked to compare keys
+    /// regardless of case, MongoDB with a case-insensitive collation.
+    pub fn case_insensitive_roles(&mut self, src: bool) -> &mut Self {
+        self.case_insensitive_roles = src;
+        self
+    }
+
+    /// Role merged by `Auth::permissions` and the checks built on it when none
+    /// of the requested roles is cached, e.g. a read-only guest role. Unlike
+    /// the app `default_role`, it isn't merged otherwise. None by default.
+    pub fn default_role<T: Into<String>>(&mut self, name: T) -> &mut Self {
+        self.fallback_role = Some(name.into());
+        self
+    }
+
+    /// Remember the permissions merged by `Auth::permissions` for each set of
+    /// role names, whatever their order, until the roles cache changes or an
+    /// item of the merged roles expires. Up to 1024 sets are kept, least
+    /// recently used evicted first, and none with an unknown role. Off by
+    /// default and in lazy mode.
+    pub fn cache_merged(&mut self, src: bool) -> &mut Self {
+        self.cache_merged = src;
+        self
+    }
+
+    /// Keep the permissions of up to `capacity` users, least recently used
+    /// evicted first, see `Auth::permissions_for_user_cached`. Ignored in lazy
+    /// mode.
+    pub fn user_permission_cache(&mut self, capacity: NonZeroUsize) -> &mut Self {
+        self.user_permission_cache = Some(capacity);
+        self
+    }
+
+    /// Share the permissions computed by `Auth::permissions_for_user_cached`
+    /// between processes through the Redis server at `src`, e.g.
+    /// `redis://127.0.0.1/`. Redis being unavailable after `build` only makes
+    /// lookups fall back to merging locally.
+    #[cfg(feature = "redis")]
+    pub fn redis_url<T: Into<String>>(&mut self, src: T) -> &mut Self {
+        self.redis_url = Some(RedisUrl(src.into()));
+        self
+    }
+
+    /// How long permissions are kept in Redis. Defaults to one hour.
+    #[cfg(feature = "redis")]
+    pub fn redis_ttl(&mut self, src: Duration) -> &mut Self {
+        self.redis_ttl = src;
+        self
+    }
+
+    /// Call `src(loaded, total)` while the roles are loaded from MongoDB, once
+    /// before the first role and after each one. `total` is counted beforehand
+    /// and is `None` when the count failed. Lazy mode loads no roles up front.
+    pub fn on_load_progress<F>(&mut self, src: F) -> &mut Self
+    where
+        F: Fn(usize, Option<usize>) + Send + Sync + 'static,
+    {
+        self.load_progress = Some(LoadProgress(Arc::new(src)));
+        self
+    }
+
+    /// Call `src(count)` with the number of cached roles each time the cache
+    /// is replaced: on `build` with static roles, on every reload by `init`,
+    /// the watcher or `refresh`, and on `Auth::import_roles`. The count is 0
+    /// in lazy mode, roles are fetched later. Called after the cache is
+    /// unlocked.
+    pub fn on_refresh<F>(&mut self, src: F) -> &mut Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.on_refresh = Some(RefreshHook(Arc::new(src)));
+        self
+    }
+
+    /// Measure the lazy mode TTL and the reconcile interval, and check role
+    /// expiry, with `src` instead of the wall clock, e.g. `testing::MockClock`
+    /// in tests.
+    pub fn clock(&mut self, src: Arc<dyn Clock>) -> &mut Self {
+        self.clock = src;
+        self
+    }
+
+    /// Load the app roles from the JSON blob `src`, e.g. an `include_str!`,
+    /// instead of MongoDB. `build` then parses them, failing with
+    /// `AuthError::InvalidStaticRoles`, and connects to no database. See the
+    /// `static_roles` module for the blob format.
+    pub fn static_roles<T: Into<String>>(&mut self, src: T) -> &mut Self {
+        self.static_roles = Some(src.into());
+        self
+    }
+
+    /// Load the app and its roles from `src` instead of MongoDB, e.g. a
+    /// `store::StaticStore` in tests. The MongoDB settings are then unused.
+    pub fn store<S: RoleStore + 'static>(&mut self, src: S) -> &mut Self {
+        self.store = Some(Arc::new(src));
+        self
+    }
+
+    /// Convert role documents stored with an older schema while loading them.
+    pub fn legacy_role_format(&mut self, src: LegacyFormat) -> &mut Self {
+        self.legacy_format = Some(src);
+        self
+    }
+
     pub async fn build(&mut self) -> Result<Auth> {
+        validate_change_stream_pipeline(&self.change_stream_pipeline)?;
+
+        let mut change_stream_options = self.change_stream_options.clone();
+
+        if let Some(t) = &self.change_stream_read_preference {
+            change_stream_options
+                .get_or_insert_with(ChangeStreamOptions::default)
+                .selection_criteria = Some(SelectionCriteria::ReadPreference(t.clone()));
+        }
+
+        if self.change_stream_pre_image {
+            change_stream_options
+                .get_or_insert_with(ChangeStreamOptions::default)
+                .full_document_before_change = Some(FullDocumentBeforeChangeType::WhenAvailable);
+        }
+
+        let mut roles = Roles::with_clock(self.clock.clone());
+        roles.on_refresh = self.on_refresh.clone();
+        let mut preload = None;
+
+        let (store, roles_database): (Arc<dyn RoleStore>, _) =
+            match (&self.static_roles, &self.store) {
+                (Some(t), _) => {
+                    let (app, src) = static_roles::parse(&self.app_name, t)?;
+                    preload = Some((app.clone(), src.clone()));
+
+                    (Arc::new(StaticStore::new(app, src)), None)
+                }
+                (None, Some(t)) => (t.clone(), None),
+                (None, None) => {
+                    let client = self.client().await?;
+
+                    let store = MongoStore {
+                        apps: client
+                            .database(self.apps_db_name.as_ref().unwrap_or(&self.mongodb.db_name)),
+                        roles: client
+                            .database(self.roles_db_name.as_ref().unwrap_or(&self.mongodb.db_name)),
+                        legacy_format: self.legacy_format.clone(),
+                        load_progress: self.load_progress.clone(),
+                    };
+
+                    let roles_database = Some(store.roles.clone());
+
+                    (Arc::new(store), roles_database)
+                }
+            };
+
+        #[cfg(feature = "redis")]
+        let redis = match &self.redis_url {
+            Some(t) => Some(redis_cache::RedisCache::connect(&t.0, self.redis_ttl).await?),
+            None => None,
+        };
+
+        let auth = Auth {
+            roles,
+            store,
+            roles_database,
+            app_name: Arc::new(RwLock::new(self.app_name.to_owned())),
+            reconcile_interval: self.reconcile_interval,
+            refresh_strategy: self.refresh_strategy,
+            last_reload: Arc::new(Mutex::new(None)),
+            change_stream_pipeline: self.change_stream_pipeline.clone(),
+            change_stream_options,
+            change_stream_retries: self.change_stream_retries,
+            on_change_stream_failure: self.on_change_stream_failure.clone(),
+            // static roles are all loaded already
+            lazy_ttl: self.lazy_ttl.filter(|_| self.static_roles.is_none()),
+            cache_key: self.cache_key,
+            case_insensitive_roles: self.case_insensitive_roles,
+            fallback_role: self.fallback_role.clone(),
+            cache_merged: self.cache_merged,
+            subscriptions: Arc::new(Mutex::new(vec![])),
+            watcher: Arc::new(Watcher::default()),
+            #[cfg(feature = "metrics")]
+            lookups: Arc::new(metrics::Histogram::default()),
+            user_cache: self
+                .user_permission_cache
+                .map(|t| Arc::new(std::sync::Mutex::new(LruCache::new(t)))),
+            #[cfg(feature = "redis")]
+            redis,
+        };
+
+        if let Some((app, src)) = preload {
+            let map = auth.role_map(src);
+            auth.roles.set(app, map).await;
+        }
+
+        if self.verify_app && auth.store.find_app(&self.app_name).await?.is_none() {
+            return Err(AuthError::MissingAppInDatabase);
+        }
+
+        Ok(auth)
+    }
+
+    /// Build a `MultiAuth` for `app_names` instead of the builder app, every
+    /// app configured like this builder and sharing one MongoDB client.
+    pub async fn build_multi<I, T>(&mut self, app_names: I) -> Result<MultiAuth>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let app_name = self.app_name.clone();
+        let client = self.client.clone();
+
+        let result = self.build_apps(app_names).await;
+
+        self.app_name = app_name;
+        self.client = client;
+
+        result
+    }
+
+    async fn build_apps<I, T>(&mut self, app_names: I) -> Result<MultiAuth>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        if self.static_roles.is_none() && self.store.is_none() {
+            self.client = Some(self.client().await?);
+        }
+
+        let mut apps = BTreeMap::new();
+
+        for name in app_names {
+            self.app_name = name.into();
+            apps.insert(self.app_name.clone(), self.build().await?);
+        }
+
+        Ok(MultiAuth::new(apps))
+    }
+
+    /// The `with_client` client, or a new one from `mongodb_uri`.
+    async fn client(&self) -> Result<Client> {
+        if let Some(t) = &self.client {
+            return Ok(t.clone());
+        }
+
         let mut client_options = ClientOptions::parse(&self.mongodb.uri)
             .await
             .map_err(AuthError::MongoParseUri)?;
 
         client_options.app_name = Some(self.mongodb.client_name.to_owned());
 
-        let client = Client::with_options(client_options).map_err(AuthError::MongoCreateClient)?;
+        if let Some(t) = self.server_selection_timeout {
+            client_options.server_selection_timeout = Some(t);
+        }
+
+        if let Some(t) = self.connect_timeout {
+            client_options.connect_timeout = Some(t);
+        }
 
-        let database = client.database(&self.mongodb.db_name);
+        if let Some(t) = &self.credential {
+            let credential = client_options
+                .credential
+                .get_or_insert_with(Credential::default);
+            credential.username = t.username.clone();
+            credential.password = t.password.clone();
+        }
 
-        Ok(Auth {
-            roles: Roles::default(),
-            database,
-            app_name: self.app_name.to_owned(),
-        })
+        if let Some(t) = &self.tls {
+            client_options.tls = Some(Tls::Enabled(t.clone()));
+        }
+
+        Client::with_options(client_options).map_err(AuthError::MongoCreateClient)
     }
 }
 
@@ -153,77 +1639,405 @@ impl Auth {
     pub fn builder<T: Into<String>>(app_name: T) -> AuthBuilder {
         AuthBuilder {
             mongodb: MongoDB::default(),
+            client: None,
+            server_selection_timeout: None,
+            connect_timeout: None,
+            verify_app: false,
+            credential: None,
+            tls: None,
+            apps_db_name: None,
+            roles_db_name: None,
             app_name: app_name.into(),
+            reconcile_interval: None,
+            refresh_strategy: RefreshStrategy::default(),
+            change_stream_pipeline: vec![],
+            change_stream_options: None,
+            change_stream_read_preference: None,
+            change_stream_pre_image: false,
+            change_stream_retries: 5,
+            on_change_stream_failure: None,
+            lazy_ttl: None,
+            legacy_format: None,
+            load_progress: None,
+            on_refresh: None,
+            cache_key: CacheKey::default(),
+            case_insensitive_roles: false,
+            fallback_role: None,
+            cache_merged: false,
+            user_permission_cache: None,
+            static_roles: None,
+            store: None,
+            clock: Arc::new(SystemClock),
+            #[cfg(feature = "redis")]
+            redis_url: None,
+            #[cfg(feature = "redis")]
+            redis_ttl: Duration::from_secs(3600),
         }
     }
 
     async fn update_roles(&self) -> Result<()> {
+        self.load_roles().await?;
+        self.notify_subscribers().await;
+        Ok(())
+    }
+
+    async fn load_roles(&self) -> Result<()> {
+        // one reload at a time
+        let mut last_reload = self.last_reload.lock().await;
+
         // get app id
+        let app_name = self.app_name.read().await.to_owned();
         let app = self
-            .database
-            .collection::<App>(APPS)
-            .find_one(doc! { "name": &self.app_name }, None)
-            .await
-            .map_err(AuthError::MongoFindOne)?;
-
-        match app {
-            Some(t) => {
-                let mut cursor = self
-                    .database
-                    .collection::<Role>(ROLES)
-                    .find(
-                        doc! {
-                            "app": t.id()
-                        },
-                        None,
-                    )
-                    .await
-                    .map_err(AuthError::MongoFind)?;
-
-                let mut roles = HashMap::new();
-
-                while let Some(role) = cursor
-                    .try_next()
-                    .await
-                    .map_err(AuthError::MongoReadCursor)?
-                {
-                    roles.insert(role.name.clone(), role);
-                }
+            .store
+            .find_app(&app_name)
+            .await?
+            .ok_or(AuthError::MissingAppInDatabase)?;
+
+        let roles = match self.lazy_ttl {
+            Some(_) => HashMap::new(),
+            None => self.role_map(self.store.load_roles(app.id()).await?),
+        };
+
+        self.roles.set(app, roles).await;
+
+        *last_reload = Some(self.roles.clock.now());
 
-                self.roles.set(roles).await;
+        Ok(())
+    }
+
+    /// Reload the roles now, e.g. after an admin tool wrote one, instead of
+    /// waiting for the watcher. Reloads run one at a time, so this is safe
+    /// alongside the watcher.
+    pub async fn refresh(&self) -> Result<()> {
+        self.update_roles().await
+    }
 
-                Ok(())
+    /// Reload the roles and fail with `AuthError::MissingRoles` if any of the
+    /// expected roles is absent. Meant to be run before accepting traffic.
+    pub async fn warm(&self, expected_roles: &[&str]) -> Result<()> {
+        self.update_roles().await?;
+
+        let mut missing = vec![];
+
+        for name in expected_roles {
+            if self.role(name).await.is_none() {
+                missing.push(name.to_string());
             }
-            None => Err(AuthError::MissingAppInDatabase),
         }
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(AuthError::MissingRoles(missing))
+        }
+    }
+
+    /// Open the roles change stream, `None` for stores other than MongoDB.
+    /// Standalone servers, which don't support change streams, are reported as
+    /// `AuthError::ChangeStreamsUnsupported`.
+    async fn watch(&self) -> Result<Option<ChangeStream<ChangeStreamEvent<Document>>>> {
+        let roles_database = match &self.roles_database {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        roles_database
+            .collection::<Document>(ROLES)
+            .watch(
+                self.change_stream_pipeline.clone(),
+                self.change_stream_options.clone(),
+            )
+            .await
+            .map(Some)
+            .map_err(|err| match err.kind.as_ref() {
+                ErrorKind::Command(t) if t.code == CHANGE_STREAMS_UNSUPPORTED_CODE => {
+                    AuthError::ChangeStreamsUnsupported
+                }
+                _ => AuthError::MongoWatchChangeStream(err),
+            })
     }
 
+    /// Load the roles and spawn the background watcher keeping them up to
+    /// date following the `RefreshStrategy`. The watcher stops when the last
+    /// clone of this `Auth` is dropped. With `RefreshStrategy::ChangeStream`,
+    /// fails with `AuthError::ChangeStreamsUnsupported` on a standalone
+    /// server. Stores other than MongoDB have no change stream, only polling
+    /// and the reconcile interval refresh them.
     pub async fn init(&self) -> Result<()> {
         self.update_roles().await?;
 
-        let ref_self = self.clone();
+        // the task must not keep its own watcher alive
+        let ref_self = Auth {
+            watcher: Arc::new(Watcher::default()),
+            ..self.clone()
+        };
 
-        tokio::spawn(async move {
-            let mut change_stream = match ref_self
-                .database
-                .collection::<Role>(ROLES)
-                .watch(vec![], None)
-                .await
-                .map_err(AuthError::MongoWatchChangeStream)
-            {
+        let (change_stream, poll_interval) = self.refresh_sources().await?;
+
+        // nothing to wait for
+        if change_stream.is_none() && poll_interval.is_none() && self.reconcile_interval.is_none() {
+            return Ok(());
+        }
+
+        let auth = ref_self.clone();
+        let handle = tokio::spawn(watch_loop(ref_self, auth, change_stream, poll_interval));
+
+        self.watcher.replace(Some(handle));
+
+        Ok(())
+    }
+
+    /// Reopen the change stream that failed with `err`, following the builder
+    /// `change_stream_retries`. `None` once every attempt failed.
+    async fn reopen_change_stream(
+        &self,
+        mut err: AuthError,
+    ) -> Option<ChangeStream<ChangeStreamEvent<Document>>> {
+        for attempt in 0..self.change_stream_retries {
+            error!("{}, reopening the change stream.", err);
+            tokio::time::sleep(reopen_delay(attempt)).await;
+
+            match self.watch().await {
+                Ok(t) => return t,
+                Err(t) => err = t,
+            }
+        }
+
+        error!("{}, giving up on the change stream.", err);
+
+        if let Some(t) = &self.on_change_stream_failure {
+            (t.0)(&err);
+        }
+
+        None
+    }
+
+    /// The change stream and the poll interval the `RefreshStrategy` asks for.
+    async fn refresh_sources(
+        &self,
+    ) -> Result<(
+        Option<ChangeStream<ChangeStreamEvent<Document>>>,
+        Option<Duration>,
+    )> {
+        let change_stream = match self.refresh_strategy {
+            RefreshStrategy::ChangeStream => self.watch().await?,
+            RefreshStrategy::Poll { .. } => None,
+            RefreshStrategy::ChangeStreamOrPoll { .. } => match self.watch().await {
                 Ok(t) => t,
                 Err(err) => {
-                    return error!("{}", err);
+                    error!("{}, polling the roles instead.", err);
+                    None
                 }
-            };
+            },
+        };
 
-            while let Some(Ok(_)) = change_stream.next().await {
-                if let Err(err) = ref_self.update_roles().await {
-                    error!("{}", err);
-                }
+        let poll_interval = match self.refresh_strategy {
+            RefreshStrategy::Poll { interval } => Some(interval),
+            RefreshStrategy::ChangeStreamOrPoll { interval } if change_stream.is_none() => {
+                Some(interval)
             }
-        });
+            _ => None,
+        };
 
-        Ok(())
+        Ok((change_stream, poll_interval))
+    }
+
+    /// Evict the changed role in lazy mode, reload every role otherwise.
+    async fn handle_event(&self, event: &ChangeStreamEvent<Document>) {
+        if self.lazy_ttl.is_none() {
+            if let Err(err) = self.update_roles().await {
+                error!("{}", err);
+            }
+            return;
+        }
+
+        let id = event
+            .document_key
+            .as_ref()
+            .and_then(|t| t.get_object_id("_id").ok());
+        let name = event
+            .full_document_before_change
+            .as_ref()
+            .and_then(|t| t.get_str("name").ok())
+            .map(|t| self.normalize_key(t));
+
+        self.roles.evict(id, name.as_deref()).await;
+        self.notify_subscribers().await;
+    }
+
+    /// Stop the background watcher started by `init`, if any. The cache keeps
+    /// the roles loaded so far, `init` starts a new watcher.
+    pub fn stop(&self) {
+        self.watcher.replace(None);
+    }
+
+    /// Whether the watcher started by `init` is still running. It ends when
+    /// stopped, or when it gives up on the change stream and has nothing else
+    /// to refresh the roles with, see `AuthBuilder::change_stream_retries`.
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_running()
+    }
+
+    /// Reload unless a reload already completed during the last reconcile interval.
+    async fn reconcile(&self) -> Result<()> {
+        if let (Some(interval), Some(last)) =
+            (self.reconcile_interval, *self.last_reload.lock().await)
+        {
+            if self.roles.clock.now().saturating_duration_since(last) < interval {
+                return Ok(());
+            }
+        }
+
+        self.update_roles().await
+    }
+}
+
+/// The server needs the `_id` resume token of every change event.
+fn validate_change_stream_pipeline(pipeline: &[Document]) -> Result<()> {
+    for stage in pipeline {
+        let removes_id = match (stage.get_document("$project"), stage.get("$unset")) {
+            (Ok(t), _) => matches!(
+                t.get("_id"),
+                Some(Bson::Boolean(false)) | Some(Bson::Int32(0)) | Some(Bson::Int64(0))
+            ),
+            (_, Some(Bson::String(t))) => t == "_id",
+            (_, Some(Bson::Array(t))) => t.iter().any(|t| t.as_str() == Some("_id")),
+            _ => false,
+        };
+
+        if removes_id {
+            return Err(AuthError::InvalidChangeStreamPipeline(stage.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `path` resolves to `Boolean(true)` in `items`, see `Auth::is_allowed`.
+fn allowed<P: ?Sized + AsRef<Path>>(items: &RoleItems, path: &P) -> Result<bool> {
+    match items.find_value(path)? {
+        DataValue::Boolean(t) => Ok(t),
+        _ => Err(AuthError::InvalidDataValueType),
+    }
+}
+
+/// The first expiry after `now` among `items`, see `RoleItems::next_expiry`.
+fn next_expiry<'r, I: IntoIterator<Item = &'r RoleItems>>(
+    items: I,
+    now: DateTime,
+) -> Option<DateTime> {
+    items.into_iter().filter_map(|t| t.next_expiry(now)).min()
+}
+
+/// The app `default_role` with `roles` merged on top, both without what
+/// expired at `now`.
+fn merge_user_roles(app: App, roles: &[Role], now: DateTime) -> RoleItems {
+    let mut items = RoleItems::default();
+    app.default_role.add_at(&mut items, now);
+    roles::merge_roles_at(roles, now).add_at(&mut items, now);
+    items
+}
+
+/// 1s before the first attempt to reopen the change stream, doubling up to 30s.
+fn reopen_delay(attempt: u32) -> Duration {
+    Duration::from_secs(1 << attempt.min(5)).min(Duration::from_secs(30))
+}
+
+/// What the watcher task keeps up to date, see `watch_loop`. Errors are
+/// logged.
+#[async_trait]
+trait WatchTarget: Send + Sync + 'static {
+    async fn on_event(&self, event: &ChangeStreamEvent<Document>);
+
+    /// Reload every role, e.g. on the poll interval.
+    async fn on_reload(&self);
+
+    async fn on_reconcile(&self);
+}
+
+#[async_trait]
+impl WatchTarget for Auth {
+    async fn on_event(&self, event: &ChangeStreamEvent<Document>) {
+        self.handle_event(event).await;
+    }
+
+    async fn on_reload(&self) {
+        if let Err(err) = self.update_roles().await {
+            error!("{}", err);
+        }
+    }
+
+    async fn on_reconcile(&self) {
+        if let Err(err) = self.reconcile().await {
+            error!("{}", err);
+        }
+    }
+}
+
+/// Watcher task of `Auth::init` and `MultiAuth::init`: hand the change events
+/// to `target` and reload or reconcile it on the intervals. `auth` has the
+/// settings, the change stream is reopened like `auth` says when it fails.
+async fn watch_loop<T: WatchTarget>(
+    target: T,
+    auth: Auth,
+    mut change_stream: Option<ChangeStream<ChangeStreamEvent<Document>>>,
+    poll_interval: Option<Duration>,
+) {
+    let mut reconcile = auth.reconcile_interval.map(delayed_interval);
+    let mut poll = poll_interval.map(delayed_interval);
+
+    loop {
+        tokio::select! {
+            event = next_event(&mut change_stream) => match event {
+                Some(Ok(t)) => target.on_event(&t).await,
+                event => {
+                    let err = match event {
+                        Some(Err(t)) => AuthError::MongoWatchChangeStream(t),
+                        _ => AuthError::ChangeStreamClosed,
+                    };
+
+                    change_stream = auth.reopen_change_stream(err).await;
+
+                    if change_stream.is_some() {
+                        // changes made meanwhile came with no event
+                        target.on_reload().await;
+                    } else if let RefreshStrategy::ChangeStreamOrPoll { interval } =
+                        auth.refresh_strategy
+                    {
+                        poll = Some(delayed_interval(interval));
+                    } else if reconcile.is_none() {
+                        break;
+                    }
+                }
+            },
+            _ = tick(&mut reconcile) => target.on_reconcile().await,
+            _ = tick(&mut poll) => target.on_reload().await,
+        }
+    }
+}
+
+/// Interval ticking every `period`, the first time after one `period`.
+fn delayed_interval(period: Duration) -> Interval {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    interval
+}
+
+/// Next change event, never ready without a change stream.
+async fn next_event(
+    src: &mut Option<ChangeStream<ChangeStreamEvent<Document>>>,
+) -> Option<mongodb::error::Result<ChangeStreamEvent<Document>>> {
+    match src {
+        Some(t) => t.next().await,
+        None => futures::future::pending().await,
+    }
+}
+
+async fn tick(interval: &mut Option<Interval>) {
+    match interval {
+        Some(t) => {
+            t.tick().await;
+        }
+        None => futures::future::pending().await,
     }
 }
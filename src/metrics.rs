@@ -0,0 +1,69 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Bucket `i` counts durations up to `2^i` microseconds, the last one
+/// everything above.
+const BUCKETS: usize = 32;
+
+/// Lock-free histogram of durations on power-of-two microsecond buckets.
+#[derive(Debug)]
+pub(crate) struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl Histogram {
+    pub(crate) fn record(&self, src: Duration) {
+        let micros = src.as_micros().max(1);
+        let index = (u128::BITS - (micros - 1).leading_zeros()) as usize;
+        self.buckets[index.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn counts(&self) -> [u64; BUCKETS] {
+        std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed))
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, `q` in `[0, 1]`.
+    fn quantile(counts: &[u64; BUCKETS], q: f64) -> Duration {
+        let total: u64 = counts.iter().sum();
+        let rank = ((total as f64) * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+
+        for (i, count) in counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(1 << i);
+            }
+        }
+
+        Duration::ZERO
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        let counts = self.counts();
+
+        CacheStats {
+            lookups: counts.iter().sum(),
+            p50: Self::quantile(&counts, 0.50),
+            p95: Self::quantile(&counts, 0.95),
+            p99: Self::quantile(&counts, 0.99),
+        }
+    }
+}
+
+/// Lookup timings of `Auth::permissions` and everything built on it.
+/// Percentiles are bucket upper bounds, precise within a factor of two.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheStats {
+    pub lookups: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
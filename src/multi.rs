@@ -0,0 +1,160 @@
+//! Several apps behind one change stream.
+//!
+//! A `MultiAuth` holds one `Auth` per app, each with its own roles cache, so
+//! roles are kept by app and then by `CacheKey`. A single watcher keeps all of
+//! them up to date: a change event reloads the app the role belongs to, or
+//! every app when the event doesn't tell, e.g. an update without
+//! `FullDocumentType::UpdateLookup` or a delete without pre-images.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::Document;
+use mongodb::change_stream::event::ChangeStreamEvent;
+
+use crate::roles::RoleItems;
+use crate::{watch_loop, Auth, AuthError, Result, WatchTarget, Watcher};
+
+#[derive(Clone, Debug)]
+pub struct MultiAuth {
+    apps: Arc<BTreeMap<String, Auth>>,
+    watcher: Arc<Watcher>,
+}
+
+impl MultiAuth {
+    pub(crate) fn new(apps: BTreeMap<String, Auth>) -> Self {
+        Self {
+            apps: Arc::new(apps),
+            watcher: Arc::new(Watcher::default()),
+        }
+    }
+
+    /// The `Auth` of the app named `name`, for everything `MultiAuth` doesn't
+    /// forward. Don't `init` it, the `MultiAuth` watcher refreshes it already,
+    /// nor `reconfigure` it, it stays under its original name.
+    pub fn app(&self, name: &str) -> Option<&Auth> {
+        self.apps.get(name)
+    }
+
+    /// Names of the apps, sorted.
+    pub fn app_names(&self) -> impl Iterator<Item = &str> {
+        self.apps.keys().map(|t| t.as_str())
+    }
+
+    fn try_app(&self, name: &str) -> Result<&Auth> {
+        self.app(name)
+            .ok_or_else(|| AuthError::UnknownApp(name.to_string()))
+    }
+
+    /// `Auth::permissions` of the app named `app`.
+    pub async fn permissions(&self, app: &str, role_names: Vec<String>) -> Result<RoleItems> {
+        Ok(self.try_app(app)?.permissions(role_names).await)
+    }
+
+    /// `Auth::is_allowed` of the app named `app`.
+    pub async fn is_allowed<P: ?Sized + AsRef<Path>>(
+        &self,
+        app: &str,
+        role_names: Vec<String>,
+        path: &P,
+    ) -> Result<bool> {
+        self.try_app(app)?.is_allowed(role_names, path).await
+    }
+
+    /// Reload the roles of every app.
+    pub async fn refresh(&self) -> Result<()> {
+        for auth in self.apps.values() {
+            auth.update_roles().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Load the roles of every app and spawn the watcher keeping them up to
+    /// date, like `Auth::init`. The apps share the builder `RefreshStrategy`
+    /// and a single change stream.
+    pub async fn init(&self) -> Result<()> {
+        self.refresh().await?;
+
+        let first = match self.apps.values().next() {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        let (change_stream, poll_interval) = first.refresh_sources().await?;
+        let reconcile_interval = first.reconcile_interval;
+        let first = first.clone();
+
+        // nothing to wait for
+        if change_stream.is_none() && poll_interval.is_none() && reconcile_interval.is_none() {
+            return Ok(());
+        }
+
+        // the task must not keep its own watcher alive
+        let ref_self = MultiAuth {
+            watcher: Arc::new(Watcher::default()),
+            ..self.clone()
+        };
+
+        let handle = tokio::spawn(watch_loop(ref_self, first, change_stream, poll_interval));
+
+        self.watcher.replace(Some(handle));
+
+        Ok(())
+    }
+
+    /// Stop the watcher started by `init`, if any.
+    pub fn stop(&self) {
+        self.watcher.replace(None);
+    }
+
+    /// Whether the watcher started by `init` is still running.
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_running()
+    }
+
+    /// Hand the event to the app of the changed role, to every app when it
+    /// can't be told.
+    async fn handle_event(&self, event: &ChangeStreamEvent<Document>) {
+        let app_id = event_app(event);
+
+        for auth in self.apps.values() {
+            let loaded = auth.roles.app().await.and_then(|t| t.id);
+
+            if app_id.is_none() || loaded.is_none() || loaded == app_id {
+                auth.handle_event(event).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl WatchTarget for MultiAuth {
+    async fn on_event(&self, event: &ChangeStreamEvent<Document>) {
+        self.handle_event(event).await;
+    }
+
+    async fn on_reload(&self) {
+        for auth in self.apps.values() {
+            auth.on_reload().await;
+        }
+    }
+
+    async fn on_reconcile(&self) {
+        for auth in self.apps.values() {
+            auth.on_reconcile().await;
+        }
+    }
+}
+
+/// The `app` of the changed role, from the full document or its pre-image.
+fn event_app(event: &ChangeStreamEvent<Document>) -> Option<ObjectId> {
+    event
+        .full_document
+        .as_ref()
+        .or(event.full_document_before_change.as_ref())
+        .and_then(|t| t.get_object_id("app").ok())
+}
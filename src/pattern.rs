@@ -0,0 +1,102 @@
+//! Compiled path patterns matching many values at once.
+//!
+//! A pattern has the shape of a `find_value` path, `/billing/**/read.boolean`,
+//! where whole segments may be wildcards:
+//!
+//! * `*` as an item matches exactly one item, any name.
+//! * `**` as an item matches zero or more nested items.
+//! * `*` as the value name or type matches any value name or type.
+//!
+//! Literal segments match exactly, wildcards don't combine with text within a
+//! segment. Every value matching the pattern is reported once, however many
+//! ways the wildcards can expand to reach it.
+
+use crate::roles::{DataKind, Value};
+use crate::{AuthError, Result};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Name(String),
+    Any,
+    AnyDepth,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PermissionPattern {
+    items: Vec<Segment>,
+    value_name: Option<String>,
+    value_kind: Option<DataKind>,
+}
+
+impl PermissionPattern {
+    /// Compile `src`, failing like `find_value` when it isn't shaped like a
+    /// value path and with `AuthError::InvalidAuthPath` on an empty segment or
+    /// an unknown value type.
+    pub fn new(src: &str) -> Result<Self> {
+        let invalid = || AuthError::InvalidAuthPath(src.to_string());
+
+        let segments: Vec<&str> = src.trim_start_matches('/').split('/').collect();
+
+        let (value, items) = match segments.split_last() {
+            Some((t, items)) if !items.is_empty() => (t, items),
+            _ => return Err(AuthError::MissingParentPath),
+        };
+
+        let items = items
+            .iter()
+            .map(|t| match *t {
+                "" => Err(invalid()),
+                "*" => Ok(Segment::Any),
+                "**" => Ok(Segment::AnyDepth),
+                t => Ok(Segment::Name(t.to_string())),
+            })
+            .collect::<Result<Vec<Segment>>>()?;
+
+        let (name, ext) = value
+            .rsplit_once('.')
+            .ok_or(AuthError::MissingValueExtension)?;
+
+        let value_name = match name {
+            "" => return Err(AuthError::MissingValueName),
+            "*" => None,
+            t => Some(t.to_string()),
+        };
+
+        let value_kind = match ext {
+            "*" => None,
+            t => Some(DataKind::from_type_name(t).ok_or_else(invalid)?),
+        };
+
+        Ok(Self {
+            items,
+            value_name,
+            value_kind,
+        })
+    }
+
+    /// Whether an item reached through `names`, from the root, matches the
+    /// item segments.
+    pub(crate) fn matches_item(&self, names: &[&str]) -> bool {
+        matches_segments(&self.items, names)
+    }
+
+    pub(crate) fn matches_value(&self, value: &Value) -> bool {
+        self.value_name.as_ref().is_none_or(|t| *t == value.name)
+            && self.value_kind.is_none_or(|t| t == value.data.kind())
+    }
+}
+
+fn matches_segments(segments: &[Segment], names: &[&str]) -> bool {
+    match (segments.split_first(), names.split_first()) {
+        (None, None) => true,
+        (Some((Segment::AnyDepth, rest)), _) => {
+            matches_segments(rest, names)
+                || (!names.is_empty() && matches_segments(segments, &names[1..]))
+        }
+        (Some((Segment::Any, rest)), Some((_, names))) => matches_segments(rest, names),
+        (Some((Segment::Name(a), rest)), Some((b, names))) => {
+            a == b && matches_segments(rest, names)
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use mongodb::bson::DateTime;
+
+use crate::roles::{DataValue, RoleItems};
+use crate::{Auth, Result};
+
+/// Effective permissions of a user, see `Auth::permissions_for_user`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UserPermissions {
+    pub items: RoleItems,
+    /// Requested role names missing from the cache, in request order.
+    pub unknown_roles: Vec<String>,
+}
+
+/// Effective permissions of a role set, merged once for repeated lookups.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PermissionSet {
+    items: RoleItems,
+    generation: u64,
+    valid_until: Option<DateTime>,
+}
+
+impl PermissionSet {
+    pub(crate) fn new(items: RoleItems, generation: u64, valid_until: Option<DateTime>) -> Self {
+        Self {
+            items,
+            generation,
+            valid_until,
+        }
+    }
+
+    pub fn items(&self) -> &RoleItems {
+        &self.items
+    }
+
+    /// Cache generation the set was built against.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Returns true only when the path resolves to `DataValue::Boolean(true)`.
+    pub fn has<P: ?Sized + AsRef<Path>>(&self, path: &P) -> bool {
+        matches!(self.items.find_value(path), Ok(DataValue::Boolean(true)))
+    }
+
+    pub fn get<P: ?Sized + AsRef<Path>>(&self, path: &P) -> Result<DataValue> {
+        self.items.find_value(path)
+    }
+
+    /// First expiry of the roles the set was merged from, `None` when none
+    /// of them expires.
+    pub fn valid_until(&self) -> Option<DateTime> {
+        self.valid_until
+    }
+
+    /// True when the roles cache of `auth` was reloaded after this set was
+    /// built, or when an item of its roles expired since, by the `auth` clock.
+    pub fn is_stale(&self, auth: &Auth) -> bool {
+        self.generation != auth.generation()
+            || self
+                .valid_until
+                .is_some_and(|t| t <= auth.roles.clock.wall_time())
+    }
+}
@@ -0,0 +1,11 @@
+//! Commonly used types, `use userman_auth::prelude::*;` to import them all.
+
+pub use crate::apps::App;
+pub use crate::multi::MultiAuth;
+pub use crate::pattern::PermissionPattern;
+pub use crate::permissions::PermissionSet;
+pub use crate::roles::{
+    Crud, DataKind, DataValue, Item, Permission, Role, RoleItems, RoleValues, TaggedDataValue,
+    Value,
+};
+pub use crate::{Auth, AuthBuilder, AuthError, Result};
@@ -0,0 +1,138 @@
+//! Effective permissions shared between processes through Redis.
+//!
+//! The roles cache generation is local to each process, so entries are keyed
+//! by a fingerprint of what the permissions are merged from instead: the app
+//! default role and the content of each role. Any process merging the same
+//! roles finds the same entry, and an edited role no longer matches it.
+
+use std::time::Duration;
+
+use log::error;
+use redis::aio::ConnectionManager;
+
+use crate::apps::App;
+use crate::roles::{Role, RoleItems};
+use crate::{AuthError, Result};
+
+const KEY_PREFIX: &str = "userman-auth";
+
+/// FNV-1a, stable across processes and builds unlike `DefaultHasher`.
+struct Fingerprint(u64);
+
+impl Fingerprint {
+    fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for t in bytes {
+            self.0 ^= *t as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        // Field separator, so that ("ab", "c") and ("a", "bc") differ.
+        self.0 ^= 0xff;
+        self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+
+    fn write_items(&mut self, items: &RoleItems) {
+        self.write(&serde_json::to_vec(items).unwrap_or_default());
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct RedisCache {
+    connection: ConnectionManager,
+    ttl: Duration,
+}
+
+impl std::fmt::Debug for RedisCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisCache")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisCache {
+    pub(crate) async fn connect(url: &str, ttl: Duration) -> Result<Self> {
+        let client = redis::Client::open(url).map_err(AuthError::RedisConnect)?;
+        let connection = ConnectionManager::new(client)
+            .await
+            .map_err(AuthError::RedisConnect)?;
+
+        Ok(Self { connection, ttl })
+    }
+
+    /// Key of the permissions merged from `app` and `roles`, in any order.
+    pub(crate) fn key(app_name: &str, app: &App, roles: &[Role]) -> String {
+        let mut roles: Vec<&Role> = roles.iter().collect();
+        roles.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+
+        let mut hash = Fingerprint::new();
+        // `id()` makes up an id when there is none, e.g. in another store
+        hash.write(app.id.map(|t| t.bytes()).unwrap_or_default().as_slice());
+        hash.write(&app.version.to_be_bytes());
+        hash.write_items(&app.default_role);
+
+        for role in roles {
+            hash.write(role.id.map(|t| t.bytes()).unwrap_or_default().as_slice());
+            hash.write(role.name.as_bytes());
+            hash.write(&role.precedence.to_be_bytes());
+            hash.write_items(&role.items);
+        }
+
+        format!("{}:{}:{:016x}", KEY_PREFIX, app_name, hash.0)
+    }
+
+    /// Stored permissions, `None` when missing. Redis errors are logged and
+    /// reported as a miss.
+    pub(crate) async fn get(&self, key: &str) -> Option<RoleItems> {
+        let mut connection = self.connection.clone();
+
+        let src = match redis::cmd("GET")
+            .arg(key)
+            .query_async::<_, Option<String>>(&mut connection)
+            .await
+        {
+            Ok(t) => t?,
+            Err(err) => {
+                error!("{}", AuthError::Redis(err));
+                return None;
+            }
+        };
+
+        match serde_json::from_str(&src) {
+            Ok(t) => Some(t),
+            Err(err) => {
+                error!("Invalid cached permissions at {}. {}", key, err);
+                None
+            }
+        }
+    }
+
+    /// Store `items` for the builder `redis_ttl`, or `valid_for` when shorter,
+    /// e.g. until an item they're merged from expires. Redis errors are logged.
+    pub(crate) async fn set(&self, key: &str, items: &RoleItems, valid_for: Option<Duration>) {
+        let src = match serde_json::to_string(items) {
+            Ok(t) => t,
+            Err(err) => {
+                error!("{}", err);
+                return;
+            }
+        };
+
+        let ttl = valid_for.map_or(self.ttl, |t| t.min(self.ttl));
+        let mut connection = self.connection.clone();
+
+        if let Err(err) = redis::cmd("SET")
+            .arg(key)
+            .arg(src)
+            .arg("PX")
+            .arg((ttl.as_millis() as u64).max(1))
+            .query_async::<_, ()>(&mut connection)
+            .await
+        {
+            error!("{}", AuthError::Redis(err));
+        }
+    }
+}
@@ -1,201 +1,1563 @@
-use std::path::Path;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 
 use mongodb::bson::oid::ObjectId;
-use mongodb::bson::DateTime;
+use mongodb::bson::{Bson, DateTime};
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "openapi")]
 use utoipa::ToSchema;
 
+use serde_json::Map;
+
+use crate::condition::{self, Context};
+use crate::pattern::PermissionPattern;
 use crate::{AuthError, Result};
 
-use crate::{serialize_oid_as_string, serialize_option_oid_as_string};
+use crate::{
+    deserialize_oid_from_string, deserialize_option_oid_from_string, serialize_oid_as_string,
+    serialize_option_oid_as_string,
+};
+
+pub const LOCAL_ROLE: &str = "local-default";
+
+/// Values of a CRUD item unless the app configures its own verbs.
+pub const CRUD_VERBS: [&str; 4] = ["create", "read", "update", "delete"];
+
+/// Largest BSON document MongoDB stores, 16MB.
+pub const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// Standard CRUD verb, see `Auth::can_do`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Crud {
+    Create,
+    Read,
+    Update,
+    Delete,
+}
+
+impl Crud {
+    /// Value name of the verb in `CRUD_VERBS`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Crud::Create => CRUD_VERBS[0],
+            Crud::Read => CRUD_VERBS[1],
+            Crud::Update => CRUD_VERBS[2],
+            Crud::Delete => CRUD_VERBS[3],
+        }
+    }
+}
+
+impl fmt::Display for Crud {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Name of the item `find_value` falls back to, see `RoleItems::find_matching`.
+pub const WILDCARD_ITEM: &str = "*";
+
+const DATA_VALUE_TAGS: [&str; 5] = ["string", "float", "integer", "boolean", "array"];
+
+/// RFC 7386 merge of `patch` into `target`.
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match patch {
+        serde_json::Value::Object(patch) => {
+            if !target.is_object() {
+                *target = serde_json::Value::Object(Map::new());
+            }
+
+            if let serde_json::Value::Object(target) = target {
+                for (key, value) in patch {
+                    if value.is_null() {
+                        target.remove(&key);
+                    } else {
+                        merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
+                    }
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
+fn patch_object(patch: serde_json::Value, path: &str) -> Result<Map<String, serde_json::Value>> {
+    match patch {
+        serde_json::Value::Object(t) => Ok(t),
+        _ => Err(AuthError::InvalidPatch(format!(
+            "{} must be an object",
+            path
+        ))),
+    }
+}
+
+fn boolean_value(name: &str, data: bool) -> Value {
+    Value::new(name, DataValue::Boolean(data))
+}
+
+/// The earlier of two expiries, `None` being never.
+fn earlier(a: Option<DateTime>, b: Option<DateTime>) -> Option<DateTime> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// The later of two expiries, `None` being never.
+fn later(a: Option<DateTime>, b: Option<DateTime>) -> Option<DateTime> {
+    a.zip(b).map(|(a, b)| a.max(b))
+}
+
+fn crud_item<T: Into<String>>(name: T) -> Item {
+    RoleItems::crud(name, &CRUD_VERBS)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum DataValue {
+    String(String),
+    Float(f64),
+    Integer(i64),
+    Boolean(bool),
+    /// List of values, e.g. allowed regions. Added arrays are merged with
+    /// `MergeStrategy::Union` unless a value sets another strategy.
+    Array(Vec<DataValue>),
+}
+
+impl fmt::Display for DataValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataValue::String(t) => write!(f, "{:?}", t),
+            DataValue::Float(t) => write!(f, "{}", t),
+            DataValue::Integer(t) => write!(f, "{}", t),
+            DataValue::Boolean(t) => write!(f, "{}", t),
+            DataValue::Array(t) => {
+                f.write_str("[")?;
+                for (i, value) in t.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+        }
+    }
+}
+
+/// The variants of `DataValue`, without data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DataKind {
+    String,
+    Float,
+    Integer,
+    Boolean,
+    Array,
+}
+
+impl DataKind {
+    /// Name of the kind, as used by `find_value` path extensions.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DataKind::String => "string",
+            DataKind::Float => "float",
+            DataKind::Integer => "integer",
+            DataKind::Boolean => "boolean",
+            DataKind::Array => "array",
+        }
+    }
+
+    /// Inverse of `type_name`.
+    pub fn from_type_name(src: &str) -> Option<Self> {
+        match src {
+            "string" => Some(DataKind::String),
+            "float" => Some(DataKind::Float),
+            "integer" => Some(DataKind::Integer),
+            "boolean" => Some(DataKind::Boolean),
+            "array" => Some(DataKind::Array),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DataKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.type_name())
+    }
+}
+
+impl DataValue {
+    pub fn kind(&self) -> DataKind {
+        match self {
+            DataValue::String(_) => DataKind::String,
+            DataValue::Float(_) => DataKind::Float,
+            DataValue::Integer(_) => DataKind::Integer,
+            DataValue::Boolean(_) => DataKind::Boolean,
+            DataValue::Array(_) => DataKind::Array,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.kind().type_name()
+    }
+}
+
+/// Values are only comparable within the same variant.
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (DataValue::String(a), DataValue::String(b)) => a.partial_cmp(b),
+            (DataValue::Float(a), DataValue::Float(b)) => a.partial_cmp(b),
+            (DataValue::Integer(a), DataValue::Integer(b)) => a.partial_cmp(b),
+            (DataValue::Boolean(a), DataValue::Boolean(b)) => a.partial_cmp(b),
+            (DataValue::Array(a), DataValue::Array(b)) => a.partial_cmp(b),
+            _ => None,
+        }
+    }
+}
+
+impl From<DataValue> for Bson {
+    fn from(src: DataValue) -> Self {
+        match src {
+            DataValue::String(t) => Bson::String(t),
+            DataValue::Float(t) => Bson::Double(t),
+            DataValue::Integer(t) => Bson::Int64(t),
+            DataValue::Boolean(t) => Bson::Boolean(t),
+            DataValue::Array(t) => Bson::Array(t.into_iter().map(Bson::from).collect()),
+        }
+    }
+}
+
+impl TryFrom<Bson> for DataValue {
+    type Error = AuthError;
+
+    fn try_from(src: Bson) -> Result<Self> {
+        match src {
+            Bson::String(t) => Ok(DataValue::String(t)),
+            Bson::Double(t) => Ok(DataValue::Float(t)),
+            Bson::Int32(t) => Ok(DataValue::Integer(t.into())),
+            Bson::Int64(t) => Ok(DataValue::Integer(t)),
+            Bson::Boolean(t) => Ok(DataValue::Boolean(t)),
+            Bson::Array(t) => Ok(DataValue::Array(
+                t.into_iter()
+                    .map(DataValue::try_from)
+                    .collect::<Result<Vec<DataValue>>>()?,
+            )),
+            t => Err(AuthError::UnsupportedBsonType(format!(
+                "{:?}",
+                t.element_type()
+            ))),
+        }
+    }
+}
+
+/// `DataValue` with its type and value in separate fields,
+/// `{"type": "boolean", "value": true}`, for API consumers. `DataValue` keeps
+/// the representation stored in MongoDB.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum TaggedDataValue {
+    String(String),
+    Float(f64),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<TaggedDataValue>),
+}
+
+impl From<DataValue> for TaggedDataValue {
+    fn from(src: DataValue) -> Self {
+        match src {
+            DataValue::String(t) => TaggedDataValue::String(t),
+            DataValue::Float(t) => TaggedDataValue::Float(t),
+            DataValue::Integer(t) => TaggedDataValue::Integer(t),
+            DataValue::Boolean(t) => TaggedDataValue::Boolean(t),
+            DataValue::Array(t) => TaggedDataValue::Array(t.into_iter().map(Self::from).collect()),
+        }
+    }
+}
+
+impl From<TaggedDataValue> for DataValue {
+    fn from(src: TaggedDataValue) -> Self {
+        match src {
+            TaggedDataValue::String(t) => DataValue::String(t),
+            TaggedDataValue::Float(t) => DataValue::Float(t),
+            TaggedDataValue::Integer(t) => DataValue::Integer(t),
+            TaggedDataValue::Boolean(t) => DataValue::Boolean(t),
+            TaggedDataValue::Array(t) => DataValue::Array(t.into_iter().map(Self::from).collect()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct DataOptions {
+    pub min_value: DataValue,
+    pub max_value: DataValue,
+}
+
+/// How `RoleItems::add` combines two non-boolean values with the same path.
+/// Numeric strategies fall back to `Last` for strings or mismatched types.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum MergeStrategy {
+    /// The value added last replaces the previous one.
+    #[default]
+    Last,
+    Max,
+    Min,
+    Sum,
+    /// Arrays only: the previous elements followed by the new ones it lacks.
+    Union,
+}
+
+impl MergeStrategy {
+    fn combine(&self, current: &DataValue, new: &DataValue) -> DataValue {
+        match (self, current, new) {
+            (MergeStrategy::Max, DataValue::Integer(a), DataValue::Integer(b)) => {
+                DataValue::Integer(*a.max(b))
+            }
+            (MergeStrategy::Max, DataValue::Float(a), DataValue::Float(b)) => {
+                DataValue::Float(a.max(*b))
+            }
+            (MergeStrategy::Min, DataValue::Integer(a), DataValue::Integer(b)) => {
+                DataValue::Integer(*a.min(b))
+            }
+            (MergeStrategy::Min, DataValue::Float(a), DataValue::Float(b)) => {
+                DataValue::Float(a.min(*b))
+            }
+            (MergeStrategy::Sum, DataValue::Integer(a), DataValue::Integer(b)) => {
+                DataValue::Integer(a.saturating_add(*b))
+            }
+            (MergeStrategy::Sum, DataValue::Float(a), DataValue::Float(b)) => {
+                DataValue::Float(a + b)
+            }
+            (MergeStrategy::Union, DataValue::Array(a), DataValue::Array(b)) => {
+                let mut union = a.clone();
+                for t in b {
+                    if !union.contains(t) {
+                        union.push(t.clone());
+                    }
+                }
+                DataValue::Array(union)
+            }
+            _ => new.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Value {
+    pub name: String,
+    #[serde(flatten)]
+    pub data: DataValue,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub options: Option<DataOptions>,
+    /// Strategy used when this value is added over an existing one.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub merge: Option<MergeStrategy>,
+    /// Expression the caller context must satisfy for the value to apply,
+    /// see the `condition` module for the grammar.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub condition: Option<String>,
+    /// Past this time `RoleItems::add` leaves the value out. Set by merging
+    /// items with different `Item::expires_at`, so that a merged value
+    /// doesn't outlive the grants it's made of.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expires_at: Option<DateTime>,
+}
+
+impl Value {
+    /// Value without options, merge strategy or condition.
+    pub fn new<T: Into<String>>(name: T, data: DataValue) -> Self {
+        Self {
+            name: name.into(),
+            data,
+            options: None,
+            merge: None,
+            condition: None,
+            expires_at: None,
+        }
+    }
+
+    pub fn is_expired_at(&self, now: DateTime) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+
+    /// Check that the data is within the `options` bounds, inclusive, if any.
+    /// Fails with `AuthError::ValueOutOfRange` naming the value, or with
+    /// `AuthError::InvalidDataValueType` when the bounds are of another type
+    /// or the data is neither a number nor a string.
+    pub fn validate(&self) -> Result<()> {
+        let options = match &self.options {
+            Some(t) => t,
+            None => return Ok(()),
+        };
+
+        let kind = self.data.kind();
+
+        if !matches!(kind, DataKind::String | DataKind::Float | DataKind::Integer)
+            || options.min_value.kind() != kind
+            || options.max_value.kind() != kind
+        {
+            return Err(AuthError::InvalidDataValueType);
+        }
+
+        if self.data < options.min_value || self.data > options.max_value {
+            return Err(AuthError::ValueOutOfRange(self.name.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// The value data once its condition, if any, is evaluated against `ctx`.
+    fn resolve(&self, ctx: &Context) -> Result<DataValue> {
+        match &self.condition {
+            Some(t) if !condition::evaluate(t, ctx)? => match self.data {
+                DataValue::Boolean(_) => Ok(DataValue::Boolean(false)),
+                _ => Err(AuthError::MissingValue),
+            },
+            _ => Ok(self.data.clone()),
+        }
+    }
+
+    /// Check whether `other` grants at least what self grants. A `Boolean(false)`
+    /// grants nothing. A `Boolean(true)` needs `Boolean(true)` in `other`, any other
+    /// value must be equal to the `other` value or within its `DataOptions` bounds.
+    fn is_subset_of(&self, other: Option<&Value>) -> bool {
+        if self.data == DataValue::Boolean(false) {
+            return true;
+        }
+
+        let other = match other {
+            Some(t) => t,
+            None => return false,
+        };
+
+        match (&self.data, &other.data) {
+            (DataValue::Boolean(_), DataValue::Boolean(t)) => {
+                *t && (other.condition.is_none() || other.condition == self.condition)
+            }
+            (DataValue::Boolean(_), _) => false,
+            (DataValue::Array(a), DataValue::Array(b)) => a.iter().all(|t| b.contains(t)),
+            (a, b) if a == b => true,
+            (a, _) => match &other.options {
+                Some(t) => t.min_value <= *a && *a <= t.max_value,
+                None => false,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RoleValues(pub Vec<Value>);
+
+impl RoleValues {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn inner(&self) -> &Vec<Value> {
+        self.0.as_ref()
+    }
+
+    pub fn inner_mut(&mut self) -> &mut Vec<Value> {
+        self.0.as_mut()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Value> {
+        self.0.iter().find(|el| el.name == name)
+    }
+
+    pub fn find_mut(&mut self, name: &str) -> Option<&mut Value> {
+        self.0.iter_mut().find(|el| el.name == name)
+    }
+
+    /// Add self values to the new &mut RoleValues collection.
+    /// A Boolean(true) sets the value to true, a Boolean(false) doesn't change it.
+    /// Other values are combined with the value `merge` strategy, the one of the
+    /// existing value or `MergeStrategy::Last`, `MergeStrategy::Union` for
+    /// arrays, in this order.
+    /// Missing values are added.
+    pub fn merge(&self, new: &mut RoleValues) {
+        self.merge_with(new, MergeStrategy::Last);
+    }
+
+    /// `merge` with `default` instead of `MergeStrategy::Last` for the
+    /// non-array values that set no strategy of their own.
+    /// Values past their `expires_at` are left out.
+    pub fn merge_with(&self, new: &mut RoleValues, default: MergeStrategy) {
+        self.merge_at(new, default, DateTime::now());
+    }
+
+    fn merge_at(&self, new: &mut RoleValues, default: MergeStrategy, now: DateTime) {
+        for value in &self.0 {
+            if value.is_expired_at(now) {
+                continue;
+            }
+
+            match new.find_mut(&value.name) {
+                Some(t) => match value.data {
+                    DataValue::Boolean(false) => {}
+                    DataValue::Boolean(true) => {
+                        match (&t.data, t.condition == value.condition) {
+                            // the same grant, as long as either lasts
+                            (DataValue::Boolean(true), true) => {
+                                t.expires_at = later(t.expires_at, value.expires_at);
+                            }
+                            // when either grant applies, only while both last:
+                            // one grant's condition mustn't outlive it
+                            (DataValue::Boolean(true), false) => {
+                                t.condition = match (&t.condition, &value.condition) {
+                                    (Some(a), Some(b)) => Some(condition::either(a, b)),
+                                    _ => None,
+                                };
+                                t.expires_at = earlier(t.expires_at, value.expires_at);
+                            }
+                            _ => {
+                                t.condition = value.condition.clone();
+                                t.expires_at = value.expires_at;
+                            }
+                        }
+                        t.data = value.data.clone();
+                    }
+                    _ => {
+                        let strategy = value.merge.or(t.merge).unwrap_or(match value.data {
+                            DataValue::Array(_) => MergeStrategy::Union,
+                            _ => default,
+                        });
+                        t.data = strategy.combine(&t.data, &value.data);
+                        if strategy == MergeStrategy::Last {
+                            t.condition = value.condition.clone();
+                            t.expires_at = value.expires_at;
+                        } else {
+                            // made of both, valid as long as both are
+                            t.expires_at = earlier(t.expires_at, value.expires_at);
+                        }
+                    }
+                },
+                None => new.0.push(value.clone()),
+            }
+        }
+    }
+
+    /// Revoke self values from the target &mut RoleValues collection.
+    /// A Boolean(true) sets the target value to false, a Boolean(false) doesn't
+    /// change it. Other values remove the target value of the same name.
+    pub fn revoke(&self, target: &mut RoleValues) {
+        for value in &self.0 {
+            match value.data {
+                DataValue::Boolean(false) => {}
+                DataValue::Boolean(true) => {
+                    if let Some(t) = target.find_mut(&value.name) {
+                        if let DataValue::Boolean(_) = t.data {
+                            t.data = DataValue::Boolean(false);
+                            t.condition = None;
+                        }
+                    }
+                }
+                _ => target.0.retain(|t| t.name != value.name),
+            }
+        }
+    }
+
+    fn apply_patch(&mut self, patch: serde_json::Value, path: &str) -> Result<()> {
+        for (name, v_patch) in patch_object(patch, path)? {
+            if v_patch.is_null() {
+                self.0.retain(|t| t.name != name);
+                continue;
+            }
+
+            let v_path = format!("{}/{}", path, name);
+            let v_patch = serde_json::Value::Object(patch_object(v_patch, &v_path)?);
+
+            let mut target = match self.find(&name) {
+                Some(t) => serde_json::to_value(t)
+                    .map_err(|err| AuthError::InvalidPatch(format!("{}: {}", v_path, err)))?,
+                None => serde_json::Value::Object(Map::new()),
+            };
+
+            // a new data value replaces the previous one, whatever its type
+            if let (serde_json::Value::Object(t), serde_json::Value::Object(p)) =
+                (&mut target, &v_patch)
+            {
+                if DATA_VALUE_TAGS.iter().any(|tag| p.contains_key(*tag)) {
+                    for tag in DATA_VALUE_TAGS {
+                        t.remove(tag);
+                    }
+                }
+            }
+
+            merge_patch(&mut target, v_patch);
+
+            if let serde_json::Value::Object(t) = &mut target {
+                t.insert("name".to_string(), serde_json::Value::String(name.clone()));
+            }
+
+            let value: Value = serde_json::from_value(target)
+                .map_err(|err| AuthError::InvalidPatch(format!("{}: {}", v_path, err)))?;
+
+            match self.find_mut(&name) {
+                Some(t) => *t = value,
+                None => self.0.push(value),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Item {
+    pub name: String,
+    #[serde(skip_serializing_if = "RoleValues::is_empty", default)]
+    pub values: RoleValues,
+    #[serde(skip_serializing_if = "RoleItems::is_empty", default)]
+    pub items: RoleItems,
+    /// Past this time `RoleItems::add` leaves the item and its sub-items out,
+    /// e.g. for temporary elevated access. `Auth` caches of merged permissions
+    /// drop their entries when it passes.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expires_at: Option<DateTime>,
+}
+
+impl Item {
+    /// Item without values or sub-items.
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        Self {
+            name: name.into(),
+            values: RoleValues::default(),
+            items: RoleItems::default(),
+            expires_at: None,
+        }
+    }
+
+    pub fn is_expired_at(&self, now: DateTime) -> bool {
+        self.expires_at.is_some_and(|t| t <= now)
+    }
+
+    fn is_subset_of(&self, other: Option<&Item>) -> bool {
+        let values = self
+            .values
+            .inner()
+            .iter()
+            .all(|value| value.is_subset_of(other.and_then(|t| t.values.find(&value.name))));
+
+        let empty = RoleItems::default();
+        let sub_items = self
+            .items
+            .is_subset_of(other.map(|t| &t.items).unwrap_or(&empty));
+
+        values && sub_items
+    }
+
+    /// Move `expires_at` to the values and sub-items, which then expire no
+    /// later than the item did.
+    fn push_expiry_down(&mut self) {
+        let expires_at = match self.expires_at.take() {
+            Some(t) => Some(t),
+            None => return,
+        };
+
+        for value in self.values.inner_mut() {
+            value.expires_at = earlier(value.expires_at, expires_at);
+        }
+
+        for item in self.items.inner_mut() {
+            item.expires_at = earlier(item.expires_at, expires_at);
+        }
+    }
+
+    /// Set the data of the named value, adding the value if missing.
+    pub fn set_value<T: Into<String>>(&mut self, name: T, data: DataValue) -> &mut Self {
+        let name = name.into();
+
+        match self.values.find_mut(&name) {
+            Some(t) => t.data = data,
+            None => self.values.0.push(Value::new(name, data)),
+        }
+
+        self
+    }
+
+    /// Entry of the named sub-item, see `RoleItems::entry`.
+    pub fn entry<T: Into<String>>(&mut self, name: T) -> ItemEntry<'_> {
+        self.items.entry(name)
+    }
+
+    fn apply_patch(&mut self, patch: serde_json::Value, path: &str) -> Result<()> {
+        for (key, value) in patch_object(patch, path)? {
+            let sub_path = format!("{}/{}", path, key);
+
+            match (key.as_str(), value) {
+                ("values", serde_json::Value::Null) => self.values = RoleValues::default(),
+                ("values", t) => self.values.apply_patch(t, &sub_path)?,
+                ("items", serde_json::Value::Null) => self.items = RoleItems::default(),
+                ("items", t) => self.items.apply_patch_at(t, &sub_path)?,
+                _ => {
+                    return Err(AuthError::InvalidPatch(format!(
+                        "{}: expected values or items",
+                        sub_path
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A single item of a `RoleItems`, which may not exist yet.
+#[derive(Debug)]
+pub struct ItemEntry<'a> {
+    items: &'a mut Vec<Item>,
+    name: String,
+}
+
+impl<'a> ItemEntry<'a> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Modify the item if it exists.
+    pub fn and_modify<F: FnOnce(&mut Item)>(self, f: F) -> Self {
+        if let Some(t) = self.items.iter_mut().find(|t| t.name == self.name) {
+            f(t);
+        }
+        self
+    }
+
+    /// The item, inserted with `default` if missing.
+    pub fn or_insert(self, default: Item) -> &'a mut Item {
+        let index = match self.items.iter().position(|t| t.name == self.name) {
+            Some(t) => t,
+            None => {
+                self.items.push(default);
+                self.items.len() - 1
+            }
+        };
+
+        &mut self.items[index]
+    }
+
+    /// The item, inserted empty if missing.
+    pub fn or_default(self) -> &'a mut Item {
+        let default = Item::new(self.name.as_str());
+        self.or_insert(default)
+    }
+}
+
+/// A difference between a role tree and the schema tree it's validated against.
+/// Paths use the `find_value` format.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SchemaViolation {
+    UnknownItem {
+        path: String,
+    },
+    UnknownValue {
+        path: String,
+    },
+    TypeMismatch {
+        path: String,
+        expected: DataKind,
+        found: DataKind,
+    },
+}
+
+/// A value differing between two role trees, `None` on the side it's missing
+/// from. Paths use the `find_value` format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiffEntry {
+    pub path: String,
+    pub before: Option<DataValue>,
+    pub after: Option<DataValue>,
+}
+
+/// Values changed from one role tree to another, see `RoleItems::diff`. Each
+/// list is sorted by path.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RoleDiff {
+    pub added: Vec<DiffEntry>,
+    pub removed: Vec<DiffEntry>,
+    pub changed: Vec<DiffEntry>,
+}
+
+impl RoleDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A `find_value` path built from its parts instead of parsed, e.g.
+/// `Permission::new().item("users").value("create", DataKind::Boolean)` for
+/// `/users/create.boolean`. Names are used as they are, `/` and `.` included.
+/// See `RoleItems::resolve`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Permission {
+    items: Vec<String>,
+    value: Option<(String, DataKind)>,
+}
+
+impl Permission {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Descend into the item `name`, below the previous one.
+    pub fn item<T: Into<String>>(mut self, name: T) -> Self {
+        self.items.push(name.into());
+        self
+    }
+
+    /// Address the value `name` of type `kind` of the last item.
+    pub fn value<T: Into<String>>(mut self, name: T, kind: DataKind) -> Self {
+        self.value = Some((name.into(), kind));
+        self
+    }
+
+    /// Parse a `find_value` path, failing like `find_value` when it's
+    /// malformed. An unknown type is `AuthError::InvalidDataValueType`.
+    pub fn from_path<P: ?Sized + AsRef<Path>>(src: &P) -> Result<Self> {
+        let path = src.as_ref();
+        let raw = path.to_str().ok_or(AuthError::InvalidUnicodeString)?;
+
+        if raw.trim_matches('/').is_empty() {
+            return Err(AuthError::EmptyPath);
+        }
+
+        // `Path` ignores a trailing `/`, which would turn an item into a value
+        if raw.ends_with('/') {
+            return Err(AuthError::MissingValueName);
+        }
+
+        let items = match path.parent() {
+            Some(t) => t,
+            None => return Err(AuthError::MissingParentPath),
+        };
+
+        // item names, with or without a leading `/`
+        let mut names = vec![];
+
+        for part in items.components() {
+            match part {
+                Component::RootDir => {}
+                Component::Normal(t) => names.push(
+                    t.to_str()
+                        .ok_or(AuthError::InvalidUnicodeString)?
+                        .to_string(),
+                ),
+                _ => return Err(AuthError::InvalidAuthPath(path.display().to_string())),
+            }
+        }
+
+        if names.is_empty() {
+            return Err(AuthError::MissingLastItem);
+        }
+
+        // split by hand, `Path` takes `.boolean` for a name without extension
+        let value_part = path
+            .file_name()
+            .and_then(|t| t.to_str())
+            .ok_or(AuthError::MissingValueName)?;
+
+        let (value_name, value_ext) = match value_part.rsplit_once('.') {
+            Some(("", _)) => return Err(AuthError::MissingValueName),
+            Some((_, "")) | None => return Err(AuthError::MissingValueExtension),
+            Some(t) => t,
+        };
+
+        let kind = DataKind::from_type_name(value_ext).ok_or(AuthError::InvalidDataValueType)?;
+
+        Ok(Self {
+            items: names,
+            value: Some((value_name.to_string(), kind)),
+        })
+    }
+}
+
+/// The `find_value` path, `/users/create.boolean`.
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for name in &self.items {
+            write!(f, "/{}", name)?;
+        }
+
+        match &self.value {
+            Some((name, kind)) => write!(f, "/{}.{}", name, kind.type_name()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RoleItems(pub Vec<Item>);
+
+impl RoleItems {
+    /// Default role of the local app, with the `CRUD_VERBS`. See
+    /// `App::local_role` for the verbs of an app.
+    pub fn local() -> Self {
+        Self(vec![
+            crud_item("users"),
+            crud_item("roles"),
+            crud_item("apps"),
+        ])
+    }
+
+    pub fn new(src: Vec<Item>) -> Self {
+        Self(src)
+    }
+
+    /// Item with a `Boolean(true)` value per verb.
+    pub fn crud<T: Into<String>, V: AsRef<str>>(name: T, verbs: &[V]) -> Item {
+        Self::crud_verbs_with_default(name, verbs, true)
+    }
+
+    /// Item with a `Boolean(default)` value per `CRUD_VERBS` verb, e.g. `false`
+    /// to create roles denying everything until granted.
+    pub fn crud_with_default<T: Into<String>>(name: T, default: bool) -> Item {
+        Self::crud_verbs_with_default(name, &CRUD_VERBS, default)
+    }
+
+    /// Item with a `Boolean(default)` value per verb.
+    pub fn crud_verbs_with_default<T: Into<String>, V: AsRef<str>>(
+        name: T,
+        verbs: &[V],
+        default: bool,
+    ) -> Item {
+        Item {
+            name: name.into(),
+            values: RoleValues(
+                verbs
+                    .iter()
+                    .map(|t| boolean_value(t.as_ref(), default))
+                    .collect(),
+            ),
+            items: RoleItems::default(),
+            expires_at: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn to_json_value(&self) -> std::result::Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(self)
+    }
+
+    pub fn inner_mut(&mut self) -> &mut Vec<Item> {
+        self.0.as_mut()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Item> {
+        self.0.iter().find(|&el| el.name == name)
+    }
+
+    /// Like `find`, falling back to the item named `*` when no item has the
+    /// name, e.g. for per-tenant items. An exact match always wins.
+    pub fn find_matching(&self, name: &str) -> Option<&Item> {
+        self.find(name).or_else(|| self.find(WILDCARD_ITEM))
+    }
+
+    /// Entry of the named item for in-place edits, e.g.
+    /// `items.entry("users").or_default().set_value("read", DataValue::Boolean(true))`.
+    pub fn entry<T: Into<String>>(&mut self, name: T) -> ItemEntry<'_> {
+        ItemEntry {
+            items: &mut self.0,
+            name: name.into(),
+        }
+    }
+
+    /// Resolve a `/item/sub-item/value.type` path. Values with a condition are
+    /// evaluated against an empty context, use `find_value_with_context`.
+    /// Items missing along the path are looked up as `*`, see `find_matching`.
+    pub fn find_value<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<DataValue> {
+        self.find_value_with_context(src, &Context::new())
+    }
+
+    /// Like `find_value`, evaluating value conditions against `ctx`. When the
+    /// condition doesn't hold a boolean resolves to `Boolean(false)` and any
+    /// other value is reported as `AuthError::MissingValue`.
+    pub fn find_value_with_context<P: ?Sized + AsRef<Path>>(
+        &self,
+        src: &P,
+        ctx: &Context,
+    ) -> Result<DataValue> {
+        self.find_value_entry(src)?.resolve(ctx)
+    }
+
+    /// `find_value` of a boolean, `AuthError::InvalidDataValueType` otherwise.
+    pub fn find_bool<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<bool> {
+        match self.find_value(src)? {
+            DataValue::Boolean(t) => Ok(t),
+            _ => Err(AuthError::InvalidDataValueType),
+        }
+    }
+
+    /// `find_value` of an integer, `AuthError::InvalidDataValueType` otherwise.
+    pub fn find_i64<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<i64> {
+        match self.find_value(src)? {
+            DataValue::Integer(t) => Ok(t),
+            _ => Err(AuthError::InvalidDataValueType),
+        }
+    }
+
+    /// `find_value` of a float, `AuthError::InvalidDataValueType` otherwise.
+    pub fn find_f64<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<f64> {
+        match self.find_value(src)? {
+            DataValue::Float(t) => Ok(t),
+            _ => Err(AuthError::InvalidDataValueType),
+        }
+    }
+
+    /// `find_value` of a string, `AuthError::InvalidDataValueType` otherwise.
+    pub fn find_string<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<String> {
+        match self.find_value(src)? {
+            DataValue::String(t) => Ok(t),
+            _ => Err(AuthError::InvalidDataValueType),
+        }
+    }
+
+    /// `find_value` of an array, `AuthError::InvalidDataValueType` otherwise.
+    pub fn find_array<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<Vec<DataValue>> {
+        match self.find_value(src)? {
+            DataValue::Array(t) => Ok(t),
+            _ => Err(AuthError::InvalidDataValueType),
+        }
+    }
+
+    /// Resolve an RFC 6901 JSON pointer such as `/users/profile/email`, the
+    /// last segment naming the value, which must be of the given kind.
+    /// `~1` and `~0` decode to `/` and `~` within a segment.
+    /// Items fall back to `*` like in `find_value`.
+    pub fn find_value_pointer(&self, pointer: &str, kind: DataKind) -> Result<DataValue> {
+        let segments: Vec<String> = match pointer.strip_prefix('/') {
+            Some(t) => t
+                .split('/')
+                .map(|t| t.replace("~1", "/").replace("~0", "~"))
+                .collect(),
+            None => return Err(AuthError::InvalidAuthPath(pointer.to_string())),
+        };
+
+        let (value_name, item_names) = match segments.split_last() {
+            Some((t, items)) if !items.is_empty() => (t, items),
+            _ => return Err(AuthError::MissingParentPath),
+        };
+
+        let mut cursor = self;
+        let mut last_item = None;
+
+        for name in item_names {
+            let item = cursor
+                .find_matching(name)
+                .ok_or_else(|| AuthError::InvalidAuthPath(name.to_string()))?;
+            cursor = &item.items;
+            last_item = Some(item);
+        }
+
+        let value = last_item
+            .and_then(|t| t.values.find(value_name))
+            .ok_or(AuthError::MissingValue)?;
+
+        if value.data.kind() != kind {
+            return Err(AuthError::InvalidDataValueType);
+        }
+
+        value.resolve(&Context::new())
+    }
+
+    fn find_value_entry<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<&Value> {
+        self.resolve_entry(&Permission::from_path(src)?)
+    }
+
+    /// `find_value` of a path built with `Permission`, without any parsing.
+    pub fn resolve(&self, perm: &Permission) -> Result<DataValue> {
+        self.resolve_entry(perm)?.resolve(&Context::new())
+    }
+
+    fn resolve_entry(&self, perm: &Permission) -> Result<&Value> {
+        let mut cursor = self;
+
+        let (last_name, parent_names) = match perm.items.split_last() {
+            Some(t) => t,
+            None => return Err(AuthError::MissingLastItem),
+        };
+
+        for name in parent_names {
+            cursor = match cursor.find_matching(name) {
+                Some(t) => &t.items,
+                None => return Err(AuthError::InvalidAuthPath(name.to_string())),
+            }
+        }
+
+        let last_item = match cursor.find_matching(last_name) {
+            Some(t) => t,
+            None => return Err(AuthError::InvalidAuthPath(last_name.to_string())),
+        };
+
+        let (value_name, kind) = perm.value.as_ref().ok_or(AuthError::MissingValueName)?;
+
+        let value = last_item
+            .values
+            .find(value_name)
+            .ok_or(AuthError::MissingValue)?;
+
+        if value.data.kind() != *kind {
+            return Err(AuthError::InvalidDataValueType);
+        }
+
+        Ok(value)
+    }
+
+    /// Remove the value a `find_value` path addresses, or the whole item when
+    /// the path ends with an item instead, `/billing/invoices`. Items are
+    /// matched by exact name, `*` only removes the wildcard item. Returns
+    /// whether anything was removed. With `prune_empty`, items left without
+    /// values nor sub-items are removed as well.
+    pub fn remove<P: ?Sized + AsRef<Path>>(&mut self, src: &P, prune_empty: bool) -> Result<bool> {
+        let path = src.as_ref();
+
+        let mut names = vec![];
+
+        for part in path.components() {
+            match part {
+                Component::RootDir => {}
+                Component::Normal(t) => {
+                    names.push(t.to_str().ok_or(AuthError::InvalidUnicodeString)?)
+                }
+                _ => return Err(AuthError::InvalidAuthPath(path.display().to_string())),
+            }
+        }
+
+        let (last, parent_names) = names.split_last().ok_or(AuthError::EmptyPath)?;
+
+        // a dotted item name isn't a value unless it ends with a type
+        let value = last.rsplit_once('.').and_then(|(name, ext)| {
+            let kind = DataKind::from_type_name(ext)?;
+            Some((name, kind)).filter(|_| !name.is_empty())
+        });
+
+        match value {
+            Some(_) if parent_names.is_empty() => Err(AuthError::MissingLastItem),
+            Some(_) => self.remove_at(parent_names, value, prune_empty),
+            None => self.remove_at(&names, None, prune_empty),
+        }
+    }
+
+    fn remove_at(
+        &mut self,
+        names: &[&str],
+        value: Option<(&str, DataKind)>,
+        prune_empty: bool,
+    ) -> Result<bool> {
+        let (name, rest) = match names.split_first() {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+
+        let index = match self.0.iter().position(|t| t.name == *name) {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+
+        let item = &mut self.0[index];
 
-pub const LOCAL_ROLE: &str = "local-default";
+        let removed = match (rest.is_empty(), value) {
+            (true, None) => {
+                self.0.remove(index);
+                return Ok(true);
+            }
+            (true, Some((value_name, kind))) => {
+                let values = item.values.inner_mut();
 
-fn crud_item<T: Into<String>>(name: T) -> Item {
-    Item {
-        name: name.into(),
-        values: RoleValues(vec![
-            Value {
-                name: "create".to_string(),
-                data: DataValue::Boolean(true),
-                options: None,
-            },
-            Value {
-                name: "read".to_string(),
-                data: DataValue::Boolean(true),
-                options: None,
-            },
-            Value {
-                name: "update".to_string(),
-                data: DataValue::Boolean(true),
-                options: None,
-            },
-            Value {
-                name: "delete".to_string(),
-                data: DataValue::Boolean(true),
-                options: None,
-            },
-        ]),
-        items: RoleItems::default(),
+                match values.iter().position(|t| t.name == value_name) {
+                    Some(t) if values[t].data.kind() != kind => {
+                        return Err(AuthError::InvalidDataValueType)
+                    }
+                    Some(t) => {
+                        values.remove(t);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            (false, _) => item.items.remove_at(rest, value, prune_empty)?,
+        };
+
+        if removed && prune_empty && item.values.is_empty() && item.items.is_empty() {
+            self.0.remove(index);
+        }
+
+        Ok(removed)
     }
-}
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, ToSchema)]
-#[serde(rename_all = "camelCase")]
-pub enum DataValue {
-    String(String),
-    Float(f64),
-    Integer(i64),
-    Boolean(bool),
-}
+    /// Check whether every permission granted by self is also granted by other,
+    /// e.g. before letting a grantor delegate self. Compared leaf by leaf.
+    /// Items are looked up like `find_value`: a `*` item of self is compared
+    /// with the `*` item of other and with every item of other self has no
+    /// item for.
+    pub fn is_subset_of(&self, other: &RoleItems) -> bool {
+        self.0.iter().all(|item| {
+            if !item.is_subset_of(other.find_matching(&item.name)) {
+                return false;
+            }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, ToSchema)]
-#[serde(rename_all = "camelCase")]
-pub struct DataOptions {
-    pub min_value: DataValue,
-    pub max_value: DataValue,
-}
+            item.name != WILDCARD_ITEM
+                || other
+                    .0
+                    .iter()
+                    .filter(|t| t.name != WILDCARD_ITEM && self.find(&t.name).is_none())
+                    .all(|t| item.is_subset_of(Some(t)))
+        })
+    }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, ToSchema)]
-#[serde(rename_all = "camelCase")]
-pub struct Value {
-    pub name: String,
-    #[serde(flatten)]
-    pub data: DataValue,
-    #[serde(skip_serializing_if = "Option::is_none", default)]
-    pub options: Option<DataOptions>,
-}
+    /// Check that every item and value of self exists in `schema` with the
+    /// same data type. Values missing from self are not violations.
+    pub fn validate_against(&self, schema: &RoleItems) -> Result<Vec<SchemaViolation>> {
+        let mut violations = vec![];
+        self.validate_at(schema, "", &mut violations);
+        Ok(violations)
+    }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default, ToSchema)]
-#[serde(rename_all = "camelCase")]
-pub struct RoleValues(pub Vec<Value>);
+    fn validate_at(&self, schema: &RoleItems, path: &str, violations: &mut Vec<SchemaViolation>) {
+        for item in &self.0 {
+            let item_path = format!("{}/{}", path, item.name);
 
-impl RoleValues {
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+            let s_item = match schema.find(&item.name) {
+                Some(t) => t,
+                None => {
+                    violations.push(SchemaViolation::UnknownItem { path: item_path });
+                    continue;
+                }
+            };
+
+            for value in item.values.inner() {
+                let path = format!("{}/{}.{}", item_path, value.name, value.data.type_name());
+
+                match s_item.values.find(&value.name) {
+                    Some(t) if t.data.kind() != value.data.kind() => {
+                        violations.push(SchemaViolation::TypeMismatch {
+                            path,
+                            expected: t.data.kind(),
+                            found: value.data.kind(),
+                        })
+                    }
+                    Some(_) => {}
+                    None => violations.push(SchemaViolation::UnknownValue { path }),
+                }
+            }
+
+            item.items
+                .validate_at(&s_item.items, &item_path, violations);
+        }
     }
 
-    pub fn inner(&self) -> &Vec<Value> {
-        self.0.as_ref()
+    /// Check that the CRUD items of self, those whose values are all booleans,
+    /// only have values named after one of `verbs`, e.g. an app `crud_verbs`.
+    /// Other verbs are `SchemaViolation::UnknownValue`.
+    pub fn validate_crud_verbs<V: AsRef<str>>(&self, verbs: &[V]) -> Result<Vec<SchemaViolation>> {
+        let mut violations = vec![];
+        self.validate_crud_verbs_at(verbs, "", &mut violations);
+        Ok(violations)
     }
 
-    pub fn inner_mut(&mut self) -> &mut Vec<Value> {
-        self.0.as_mut()
+    fn validate_crud_verbs_at<V: AsRef<str>>(
+        &self,
+        verbs: &[V],
+        path: &str,
+        violations: &mut Vec<SchemaViolation>,
+    ) {
+        for item in &self.0 {
+            let item_path = format!("{}/{}", path, item.name);
+            let values = item.values.inner();
+
+            if values.iter().all(|t| t.data.kind() == DataKind::Boolean) {
+                for value in values {
+                    if !verbs.iter().any(|t| t.as_ref() == value.name) {
+                        violations.push(SchemaViolation::UnknownValue {
+                            path: format!("{}/{}.boolean", item_path, value.name),
+                        });
+                    }
+                }
+            }
+
+            item.items
+                .validate_crud_verbs_at(verbs, &item_path, violations);
+        }
     }
 
-    pub fn find(&self, name: &str) -> Option<&Value> {
-        self.0.iter().find(|el| el.name == name)
+    /// Every value matching `pattern` with its `find_value` path, depth-first.
+    /// Values are resolved like `find_value`, those that don't resolve are
+    /// left out.
+    pub fn matches(&self, pattern: &PermissionPattern) -> Vec<(PathBuf, DataValue)> {
+        let mut found = vec![];
+        self.matches_at(pattern, Path::new("/"), &mut vec![], &mut found);
+        found
     }
 
-    pub fn find_mut(&mut self, name: &str) -> Option<&mut Value> {
-        self.0.iter_mut().find(|el| el.name == name)
+    fn matches_at<'a>(
+        &'a self,
+        pattern: &PermissionPattern,
+        path: &Path,
+        names: &mut Vec<&'a str>,
+        found: &mut Vec<(PathBuf, DataValue)>,
+    ) {
+        for item in &self.0 {
+            let item_path = path.join(&item.name);
+            names.push(&item.name);
+
+            if pattern.matches_item(names) {
+                for value in item.values.inner() {
+                    if !pattern.matches_value(value) {
+                        continue;
+                    }
+
+                    if let Ok(t) = value.resolve(&Context::new()) {
+                        let value_path =
+                            item_path.join(format!("{}.{}", value.name, value.data.type_name()));
+                        found.push((value_path, t));
+                    }
+                }
+            }
+
+            item.items.matches_at(pattern, &item_path, names, found);
+            names.pop();
+        }
     }
-}
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, ToSchema)]
-#[serde(rename_all = "camelCase")]
-pub struct Item {
-    pub name: String,
-    #[serde(skip_serializing_if = "RoleValues::is_empty", default)]
-    pub values: RoleValues,
-    #[serde(skip_serializing_if = "RoleItems::is_empty", default)]
-    pub items: RoleItems,
-}
+    /// `find_value` paths of every value resolving to `Boolean(true)`,
+    /// depth-first, e.g. to build the scopes sent to another service.
+    pub fn flatten_grants(&self) -> Vec<PathBuf> {
+        let mut grants = vec![];
+        self.flatten_grants_at(Path::new("/"), &mut grants);
+        grants
+    }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Default, ToSchema)]
-#[serde(rename_all = "camelCase")]
-pub struct RoleItems(pub Vec<Item>);
+    fn flatten_grants_at(&self, path: &Path, grants: &mut Vec<PathBuf>) {
+        for item in &self.0 {
+            let item_path = path.join(&item.name);
 
-impl RoleItems {
-    pub fn local() -> Self {
-        Self(vec![
-            crud_item("users"),
-            crud_item("roles"),
-            crud_item("apps"),
-        ])
+            for value in item.values.inner() {
+                if matches!(value.resolve(&Context::new()), Ok(DataValue::Boolean(true))) {
+                    grants.push(item_path.join(format!(
+                        "{}.{}",
+                        value.name,
+                        value.data.type_name()
+                    )));
+                }
+            }
+
+            item.items.flatten_grants_at(&item_path, grants);
+        }
     }
 
-    pub fn new(src: Vec<Item>) -> Self {
-        Self(src)
+    /// Every value of the tree with its `find_value` path, e.g.
+    /// `/users/create.boolean`, depth-first: an item's values before its
+    /// sub-items.
+    pub fn iter_paths(&self) -> impl Iterator<Item = (String, &Value)> {
+        let mut found = vec![];
+        self.iter_paths_at("", &mut found);
+        found.into_iter()
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.0.is_empty()
+    fn iter_paths_at<'a>(&'a self, path: &str, found: &mut Vec<(String, &'a Value)>) {
+        for item in &self.0 {
+            let item_path = format!("{}/{}", path, item.name);
+
+            for value in item.values.inner() {
+                let value_path = format!("{}/{}.{}", item_path, value.name, value.data.type_name());
+                found.push((value_path, value));
+            }
+
+            item.items.iter_paths_at(&item_path, found);
+        }
     }
 
-    pub fn inner_mut(&mut self) -> &mut Vec<Item> {
-        self.0.as_mut()
+    /// Values of `other` missing from self as added, values of self missing
+    /// from `other` as removed, and values in both with a different data as
+    /// changed. Values are matched by path, so a value whose type changes is
+    /// removed and added.
+    pub fn diff(&self, other: &RoleItems) -> RoleDiff {
+        let before: BTreeMap<String, &Value> = self.iter_paths().collect();
+        let after: BTreeMap<String, &Value> = other.iter_paths().collect();
+
+        let mut diff = RoleDiff::default();
+
+        for (path, a) in &before {
+            match after.get(path) {
+                None => diff.removed.push(DiffEntry {
+                    path: path.to_owned(),
+                    before: Some(a.data.clone()),
+                    after: None,
+                }),
+                Some(b) if a.data != b.data => diff.changed.push(DiffEntry {
+                    path: path.to_owned(),
+                    before: Some(a.data.clone()),
+                    after: Some(b.data.clone()),
+                }),
+                Some(_) => {}
+            }
+        }
+
+        for (path, b) in &after {
+            if !before.contains_key(path) {
+                diff.added.push(DiffEntry {
+                    path: path.to_owned(),
+                    before: None,
+                    after: Some(b.data.clone()),
+                });
+            }
+        }
+
+        diff
     }
 
-    pub fn find(&self, name: &str) -> Option<&Item> {
-        self.0.iter().find(|&el| el.name == name)
+    /// Apply `f` to every value of the tree, depth-first. The closure gets the
+    /// `find_value` path of the value, e.g. `/users/create.boolean`.
+    pub fn map_values<F: FnMut(&Path, &mut Value)>(&mut self, mut f: F) {
+        self.map_values_at(Path::new("/"), &mut f);
     }
 
-    pub fn find_value<P: ?Sized + AsRef<Path>>(&self, src: &P) -> Result<DataValue> {
-        let mut cursor = self;
+    fn map_values_at<F: FnMut(&Path, &mut Value)>(&mut self, path: &Path, f: &mut F) {
+        for item in &mut self.0 {
+            let item_path = path.join(&item.name);
 
-        let path = src.as_ref();
+            for value in item.values.inner_mut() {
+                let value_path: PathBuf =
+                    item_path.join(format!("{}.{}", value.name, value.data.type_name()));
+                f(&value_path, value);
+            }
 
-        let items = match path.parent() {
-            Some(t) => t,
-            None => return Err(AuthError::MissingParentPath),
-        };
+            item.items.map_values_at(&item_path, f);
+        }
+    }
 
-        for part in items.iter().take(items.iter().count() - 2) {
-            let name = part.to_str().ok_or(AuthError::InvalidUnicodeString)?;
+    /// `Value::validate` every value of the tree, failing on the first invalid
+    /// one. `AuthError::ValueOutOfRange` then has the `find_value` path of it.
+    pub fn validate_all(&self) -> Result<()> {
+        self.validate_all_at(Path::new("/"))
+    }
 
-            if name != "/" {
-                cursor = match cursor.find(name) {
-                    Some(t) => &t.items,
-                    None => return Err(AuthError::InvalidAuthPath(name.to_string())),
-                }
+    fn validate_all_at(&self, path: &Path) -> Result<()> {
+        for item in &self.0 {
+            let item_path = path.join(&item.name);
+
+            for value in item.values.inner() {
+                value.validate().map_err(|err| match err {
+                    AuthError::ValueOutOfRange(_) => {
+                        let value_path =
+                            item_path.join(format!("{}.{}", value.name, value.data.type_name()));
+                        AuthError::ValueOutOfRange(value_path.display().to_string())
+                    }
+                    err => err,
+                })?;
             }
+
+            item.items.validate_all_at(&item_path)?;
         }
 
-        let last_part = match items.iter().last() {
-            Some(t) => t.to_owned(),
-            None => return Err(AuthError::MissingLastItem),
-        };
+        Ok(())
+    }
 
-        let last_name = last_part.to_str().ok_or(AuthError::InvalidUnicodeString)?;
+    /// The earliest `expires_at` after `now` in the tree, values and sub-items
+    /// included, i.e. until when permissions merged from it are valid.
+    pub(crate) fn next_expiry(&self, now: DateTime) -> Option<DateTime> {
+        self.0
+            .iter()
+            .flat_map(|t| {
+                let values = t.values.inner().iter().map(|t| t.expires_at);
+                values.chain([t.expires_at, t.items.next_expiry(now)])
+            })
+            .flatten()
+            .filter(|t| *t > now)
+            .min()
+    }
 
-        let last_item = match cursor.find(last_name) {
-            Some(t) => t,
-            None => return Err(AuthError::InvalidAuthPath(last_name.to_string())),
-        };
+    /// Number of values in the tree, sub-items included.
+    pub fn leaf_count(&self) -> usize {
+        self.0
+            .iter()
+            .map(|t| t.values.inner().len() + t.items.leaf_count())
+            .sum()
+    }
 
-        let value_name_part = match path.file_stem() {
-            Some(t) => t,
-            None => return Err(AuthError::MissingValueName),
-        };
+    /// Sort items and values by name, recursively.
+    pub fn sort(&mut self) {
+        self.0.sort_by(|a, b| a.name.cmp(&b.name));
 
-        let value_name = value_name_part
-            .to_str()
-            .ok_or(AuthError::InvalidUnicodeString)?;
+        for item in &mut self.0 {
+            item.values.0.sort_by(|a, b| a.name.cmp(&b.name));
+            item.items.sort();
+        }
+    }
 
-        let value_ext_part = match path.extension() {
-            Some(t) => t,
-            None => return Err(AuthError::MissingValueExtension),
-        };
+    fn fmt_depth(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        for item in &self.0 {
+            writeln!(f, "{:indent$}{}", "", item.name, indent = depth * 2)?;
 
-        let value_ext = value_ext_part
-            .to_str()
-            .ok_or(AuthError::InvalidUnicodeString)?;
+            for value in item.values.inner() {
+                writeln!(
+                    f,
+                    "{:indent$}{}: {}",
+                    "",
+                    value.name,
+                    value.data,
+                    indent = (depth + 1) * 2
+                )?;
+            }
 
-        let value = last_item
-            .values
-            .find(value_name)
-            .ok_or(AuthError::MissingValue)?;
+            item.items.fmt_depth(f, depth + 1)?;
+        }
 
-        match (value_ext, &value.data) {
-            ("boolean", DataValue::Boolean(_)) => {}
-            ("float", DataValue::Float(_)) => {}
-            ("integer", DataValue::Integer(_)) => {}
-            ("string", DataValue::String(_)) => {}
-            _ => return Err(AuthError::InvalidDataValueType),
+        Ok(())
+    }
+
+    /// Apply a JSON merge patch (RFC 7386). The patch is an object keyed by item
+    /// name, each item is an object with optional `values` (keyed by value name)
+    /// and `items` (nested the same way) members. A `null` removes the item or
+    /// value, any other member is created or overwritten:
+    ///
+    /// `{"users": {"values": {"create": {"boolean": false}, "delete": null}}, "apps": null}`
+    ///
+    /// Self is left untouched when the patch is rejected.
+    pub fn apply_patch(&mut self, patch: serde_json::Value) -> Result<()> {
+        let mut patched = self.clone();
+        patched.apply_patch_at(patch, "")?;
+        *self = patched;
+        Ok(())
+    }
+
+    fn apply_patch_at(&mut self, patch: serde_json::Value, path: &str) -> Result<()> {
+        for (name, i_patch) in patch_object(patch, if path.is_empty() { "/" } else { path })? {
+            if i_patch.is_null() {
+                self.0.retain(|t| t.name != name);
+                continue;
+            }
+
+            let index = match self.0.iter().position(|t| t.name == name) {
+                Some(t) => t,
+                None => {
+                    self.0.push(Item::new(name.as_str()));
+                    self.0.len() - 1
+                }
+            };
+
+            self.0[index].apply_patch(i_patch, &format!("{}/{}", path, name))?;
         }
 
-        Ok(value.data.clone())
+        Ok(())
     }
 
     fn merge_items(&self, new: &mut Vec<Item>) {
@@ -222,44 +1584,44 @@ impl RoleItems {
         self.merge_items(new.inner_mut());
     }
 
-    fn add_items(&self, new: &mut Vec<Item>) {
+    fn add_items(&self, new: &mut Vec<Item>, default: MergeStrategy, now: DateTime) {
         for item in &self.0 {
+            if item.is_expired_at(now) {
+                continue;
+            }
+
             match new.iter_mut().find(|t| t.name == item.name) {
                 Some(t) => {
-                    for value in item.values.inner() {
-                        match t.values.find_mut(&value.name) {
-                            Some(t) => match value.data {
-                                DataValue::Boolean(t) if !t => {}
-                                _ => {
-                                    t.data = value.data.clone();
-                                }
-                            },
-                            None => {
-                                t.values.inner_mut().push(value.clone());
-                            }
+                    // the merged item must not outlive either grant, with
+                    // different expiries they move down to what they cover
+                    let mut pushed = None;
+
+                    if t.expires_at != item.expires_at {
+                        t.push_expiry_down();
+
+                        if item.expires_at.is_some() {
+                            let mut item = item.clone();
+                            item.push_expiry_down();
+                            pushed = Some(item);
                         }
                     }
 
-                    item.items.add_items(t.items.inner_mut());
+                    let item = pushed.as_ref().unwrap_or(item);
+
+                    item.values.merge_at(&mut t.values, default, now);
+                    item.items.add_items(t.items.inner_mut(), default, now);
                 }
                 None => {
-                    new.push(item.clone());
-                }
-            }
-        }
-
-        for n_item in new {
-            if let Some(a_item) = self.find(&n_item.name) {
-                // values
-                for n_value in n_item.values.inner_mut() {
-                    if let Some(a_value) = a_item.values.find(&n_value.name) {
-                        *n_value = a_value.clone();
-                    }
+                    // added through `merge_at` and `add_items` to leave
+                    // expired values and sub-items out
+                    let mut t = Item {
+                        expires_at: item.expires_at,
+                        ..Item::new(item.name.as_str())
+                    };
+                    item.values.merge_at(&mut t.values, default, now);
+                    item.items.add_items(t.items.inner_mut(), default, now);
+                    new.push(t);
                 }
-
-                // sub-items
-                let n_sub_items = n_item.items.inner_mut();
-                a_item.items.merge_items(n_sub_items);
             }
         }
     }
@@ -268,28 +1630,108 @@ impl RoleItems {
     /// Look for new &mut RoleItems values in self collection.
     /// If the value is Boolean(true), set to true.
     /// If the value is Boolean(false), don't change.
-    /// If the value is not a Boolean, replace with the last one.
+    /// If the value is not a Boolean, combine both with its `MergeStrategy`,
+    /// `MergeStrategy::Last` replaces with the last one and is the default.
     /// If the value is missing add the value.
+    /// Items and values past their `expires_at` are left out. Items with
+    /// different `expires_at` give their expiry to their values and sub-items,
+    /// so that no grant outlives the role it comes from.
     pub fn add(&self, new: &mut RoleItems) {
-        self.add_items(new.inner_mut());
+        self.add_with(new, MergeStrategy::Last);
+    }
+
+    /// `add` with `default` instead of `MergeStrategy::Last` for the non-array
+    /// values that set no strategy of their own, e.g. `MergeStrategy::Max` for
+    /// the most permissive limit to win. Booleans are still or-ed.
+    pub fn add_with(&self, new: &mut RoleItems, default: MergeStrategy) {
+        self.add_items(new.inner_mut(), default, DateTime::now());
+    }
+
+    /// `add` with expiry checked at `now` instead of the current date, e.g.
+    /// the `Clock::wall_time` of an `AuthBuilder::clock`.
+    pub fn add_at(&self, new: &mut RoleItems, now: DateTime) {
+        self.add_items(new.inner_mut(), MergeStrategy::Last, now);
+    }
+
+    fn revoke_items(&self, target: &mut Vec<Item>) {
+        // a name the target grants through its `*` item gets a copy of it to
+        // revoke, leaving the other names alone
+        if let Some(wildcard) = target.iter().find(|t| t.name == WILDCARD_ITEM).cloned() {
+            for item in &self.0 {
+                if target.iter().all(|t| t.name != item.name) {
+                    target.push(Item {
+                        name: item.name.to_owned(),
+                        ..wildcard.clone()
+                    });
+                }
+            }
+        }
+
+        for item in &self.0 {
+            for t in target.iter_mut() {
+                // a `*` item revokes every name self has no item for
+                let applies = t.name == item.name
+                    || (item.name == WILDCARD_ITEM && self.find(&t.name).is_none());
+
+                if applies {
+                    item.values.revoke(&mut t.values);
+                    item.items.revoke_items(t.items.inner_mut());
+                }
+            }
+        }
+    }
+
+    /// Revoke self RoleItems from the target &mut RoleItems collection, the
+    /// inverse of `add`, e.g. to apply a deny list over granted roles.
+    /// If the value is Boolean(true), set the target value to false.
+    /// If the value is Boolean(false), don't change.
+    /// If the value is not a Boolean, remove the target value.
+    /// Items missing from the target are ignored. Items are looked up like
+    /// `find_value`: a `*` item revokes every target item self has no item
+    /// for, and a name granted through the target `*` item is revoked on a
+    /// copy of it.
+    pub fn revoke(&self, target: &mut RoleItems) {
+        self.revoke_items(target.inner_mut());
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, ToSchema)]
+/// Human readable tree, one item or value per line, indented by nesting.
+impl fmt::Display for RoleItems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_depth(f, 0)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct Role {
     #[serde(
         rename(serialize = "id", deserialize = "_id"),
+        alias = "id",
+        default,
         skip_serializing_if = "Option::is_none",
-        serialize_with = "serialize_option_oid_as_string"
+        serialize_with = "serialize_option_oid_as_string",
+        deserialize_with = "deserialize_option_oid_from_string"
     )]
-    #[schema(value_type = String)]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub id: Option<ObjectId>,
-    #[serde(serialize_with = "serialize_oid_as_string")]
-    #[schema(value_type = String)]
+    #[serde(
+        serialize_with = "serialize_oid_as_string",
+        deserialize_with = "deserialize_oid_from_string"
+    )]
+    #[cfg_attr(feature = "openapi", schema(value_type = String))]
     pub app: ObjectId,
     pub name: String,
     pub items: RoleItems,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub tags: Vec<String>,
+    /// Roles with a higher precedence win conflicting non-boolean values.
+    #[serde(default)]
+    pub precedence: i32,
+    /// Names of the roles whose items this role inherits, see `resolve_parents`.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub parents: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub created_at: Option<DateTime>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -303,6 +1745,9 @@ impl Default for Role {
             app: ObjectId::default(),
             name: LOCAL_ROLE.to_string(),
             items: RoleItems::default(),
+            tags: vec![],
+            precedence: 0,
+            parents: vec![],
             created_at: None,
             updated_at: None,
         }
@@ -317,7 +1762,166 @@ impl Role {
     pub fn to_string_pretty(&self) -> std::result::Result<String, serde_json::Error> {
         serde_json::to_string_pretty(&self)
     }
+
+    /// Fail with `AuthError::RoleTooLarge` when the role serializes to a BSON
+    /// document larger than MongoDB accepts. Meant to be run before storing it.
+    pub fn check_size(&self) -> Result<()> {
+        // a role has no field BSON can't represent
+        let size = mongodb::bson::to_vec(self).map_or(0, |t| t.len());
+
+        if size > MAX_DOCUMENT_SIZE {
+            return Err(AuthError::RoleTooLarge {
+                name: Some(self.name.to_owned()),
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn to_json_value(&self) -> std::result::Result<serde_json::Value, serde_json::Error> {
+        serde_json::to_value(self)
+    }
 }
 
-#[derive(Serialize, ToSchema)]
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
 pub struct RolesVec(pub Vec<Role>);
+
+/// Fold the roles items with `RoleItems::add`. Roles are folded by
+/// precedence, then name, then id, whatever the input order, so that the
+/// result doesn't depend on it: on conflicting non-boolean values the role
+/// with the highest precedence wins, the one sorting last by name on ties.
+/// A role given twice is only added once.
+pub fn merge_roles<'r, I: IntoIterator<Item = &'r Role>>(roles: I) -> RoleItems {
+    merge_roles_at(roles, DateTime::now())
+}
+
+/// `merge_roles` with expiry checked at `now`, see `RoleItems::add_at`.
+pub fn merge_roles_at<'r, I: IntoIterator<Item = &'r Role>>(roles: I, now: DateTime) -> RoleItems {
+    let mut roles: Vec<&Role> = roles.into_iter().collect();
+
+    roles.sort_by(|a, b| (a.precedence, &a.name, a.id).cmp(&(b.precedence, &b.name, b.id)));
+    roles.dedup_by(|a, b| (&a.name, a.id) == (&b.name, b.id));
+
+    let mut parent = RoleItems::default();
+
+    for role in roles {
+        role.items.add_at(&mut parent, now);
+    }
+
+    parent
+}
+
+/// Fold into each role the items of its `parents`, recursively, with
+/// `RoleItems::add`: parents in order, then the role's own items, which win
+/// conflicting values. Expired items are kept, expiry applies when the
+/// roles are merged. Parents are found by name among `roles`. Fails with
+/// `AuthError::RoleCycle` when a role inherits from itself and with
+/// `AuthError::MissingRoles` on an unknown parent and with
+/// `AuthError::AmbiguousParent` when several roles have the parent name.
+pub fn resolve_parents(roles: &mut [Role]) -> Result<()> {
+    resolve_parents_each(roles, false)
+        .into_iter()
+        .flatten()
+        .next()
+        .map_or(Ok(()), Err)
+}
+
+/// `resolve_parents` role by role: the error of each role that can't be
+/// resolved, whose items are left as they are, `None` for the others. Parent
+/// names are compared regardless of case when `case_insensitive`, a name
+/// shared by several roles fails with `AuthError::AmbiguousParent`.
+pub(crate) fn resolve_parents_each(
+    roles: &mut [Role],
+    case_insensitive: bool,
+) -> Vec<Option<AuthError>> {
+    if roles.iter().all(|t| t.parents.is_empty()) {
+        return roles.iter().map(|_| None).collect();
+    }
+
+    let mut resolved = vec![None; roles.len()];
+
+    let results: Vec<Result<RoleItems>> = (0..roles.len())
+        .map(|i| resolve_role(roles, i, case_insensitive, &mut resolved, &mut vec![]))
+        .collect();
+
+    roles
+        .iter_mut()
+        .zip(results)
+        .map(|(role, items)| match items {
+            Ok(t) => {
+                role.items = t;
+                None
+            }
+            Err(err) => Some(err),
+        })
+        .collect()
+}
+
+fn resolve_role(
+    roles: &[Role],
+    i: usize,
+    case_insensitive: bool,
+    resolved: &mut Vec<Option<RoleItems>>,
+    stack: &mut Vec<usize>,
+) -> Result<RoleItems> {
+    if let Some(t) = &resolved[i] {
+        return Ok(t.clone());
+    }
+
+    if let Some(start) = stack.iter().position(|t| *t == i) {
+        let chain: Vec<&str> = stack[start..]
+            .iter()
+            .chain([&i])
+            .map(|t| roles[*t].name.as_str())
+            .collect();
+        return Err(AuthError::RoleCycle(chain.join(" -> ")));
+    }
+
+    stack.push(i);
+
+    let mut items = RoleItems::default();
+
+    for name in &roles[i].parents {
+        let mut found = roles
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| match case_insensitive {
+                true => t.name.to_lowercase() == name.to_lowercase(),
+                false => t.name == *name,
+            });
+
+        let parent = match (found.next(), found.next()) {
+            (Some((t, _)), None) => t,
+            (Some(_), Some(_)) => return Err(AuthError::AmbiguousParent(name.to_string())),
+            (None, _) => return Err(AuthError::MissingRoles(vec![name.to_string()])),
+        };
+
+        resolve_role(roles, parent, case_insensitive, resolved, stack)?
+            .add_at(&mut items, DateTime::MIN);
+    }
+
+    roles[i].items.add_at(&mut items, DateTime::MIN);
+
+    stack.pop();
+    resolved[i] = Some(items.clone());
+
+    Ok(items)
+}
+
+/// Merge the `requested` roles found in `roles` and resolve `path` against the
+/// result, like `Auth` does with its cache. Unknown role names are skipped.
+/// Expiry is checked at `now`, so that the result only depends on the
+/// arguments.
+pub fn evaluate_roles(
+    roles: &[Role],
+    requested: &[&str],
+    path: &str,
+    now: DateTime,
+) -> Result<DataValue> {
+    let selected = requested
+        .iter()
+        .filter_map(|name| roles.iter().find(|t| t.name == *name));
+
+    merge_roles_at(selected, now).find_value(path)
+}
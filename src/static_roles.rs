@@ -0,0 +1,69 @@
+//! Roles shipped with the binary instead of stored in MongoDB.
+//!
+//! The blob is a JSON object with the app `defaultRole`, optional, and its
+//! `roles`:
+//!
+//! `{"defaultRole": [...], "roles": [{"name": "admin", "items": [...]}]}`
+//!
+//! Roles may also have `tags`, a `precedence` and `parents`, like `Role`.
+//!
+//! Roles need no id nor app, they belong to the app being built. The app and
+//! the roles are given new ids when parsed.
+
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+
+use crate::apps::App;
+use crate::roles::{Role, RoleItems};
+use crate::{AuthError, Result};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StaticRoles {
+    default_role: Option<RoleItems>,
+    roles: Vec<StaticRole>,
+}
+
+#[derive(Deserialize)]
+struct StaticRole {
+    name: String,
+    items: RoleItems,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    precedence: i32,
+    #[serde(default)]
+    parents: Vec<String>,
+}
+
+/// App named `app_name` with the roles of the blob `src`.
+pub(crate) fn parse(app_name: &str, src: &str) -> Result<(App, Vec<Role>)> {
+    let src: StaticRoles = serde_json::from_str(src).map_err(AuthError::InvalidStaticRoles)?;
+
+    let mut app = App {
+        id: Some(ObjectId::new()),
+        name: app_name.to_string(),
+        ..App::default()
+    };
+
+    if let Some(t) = src.default_role {
+        app.default_role = t;
+    }
+
+    let roles = src
+        .roles
+        .into_iter()
+        .map(|t| Role {
+            id: Some(ObjectId::new()),
+            app: app.id(),
+            name: t.name,
+            items: t.items,
+            tags: t.tags,
+            precedence: t.precedence,
+            parents: t.parents,
+            ..Role::default()
+        })
+        .collect();
+
+    Ok((app, roles))
+}
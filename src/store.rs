@@ -0,0 +1,258 @@
+//! Where `Auth` loads the app and its roles from.
+//!
+//! MongoDB is the default. `AuthBuilder::store` takes any other `RoleStore`,
+//! e.g. a `StaticStore` with fixed roles in tests. Only MongoDB has a change
+//! stream, other stores are kept up to date by `RefreshStrategy::Poll`.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{doc, DateTime, Document};
+use mongodb::error::ErrorKind;
+use mongodb::options::{
+    Collation, CollationStrength, FindOneAndUpdateOptions, FindOneOptions, ReturnDocument,
+};
+use mongodb::Database;
+
+use crate::apps::App;
+use crate::legacy::LegacyFormat;
+use crate::roles::Role;
+use crate::{AuthError, CacheKey, Result, APPS, ROLES};
+
+/// "BSONObjectTooLarge"
+const BSON_OBJECT_TOO_LARGE_CODE: i32 = 10334;
+
+#[async_trait]
+pub trait RoleStore: Debug + Send + Sync {
+    /// The app named `name`, `None` when there is none.
+    async fn find_app(&self, name: &str) -> Result<Option<App>>;
+
+    /// Every role of the app.
+    async fn load_roles(&self, app_id: ObjectId) -> Result<Vec<Role>>;
+
+    /// The role of the app with the cache `key`, looked up in lazy mode, keys
+    /// compared regardless of case when `case_insensitive`. Defaults to
+    /// searching the result of `load_roles`.
+    async fn find_role(
+        &self,
+        app_id: ObjectId,
+        cache_key: CacheKey,
+        key: &str,
+        case_insensitive: bool,
+    ) -> Result<Option<Role>> {
+        let roles = self.load_roles(app_id).await?;
+
+        Ok(roles.into_iter().find(|t| match case_insensitive {
+            true => cache_key.key(t).to_lowercase() == key.to_lowercase(),
+            false => cache_key.key(t) == key,
+        }))
+    }
+
+    /// Store `role` in the app, replacing the role with the same id, or the
+    /// same name when it has none, and return its id. Stores are read-only
+    /// unless they implement it, failing with `AuthError::ReadOnlyStore`.
+    async fn upsert_role(&self, _app_id: ObjectId, _role: &Role) -> Result<ObjectId> {
+        Err(AuthError::ReadOnlyStore)
+    }
+
+    /// Delete the role of the app named `name`, `false` when there is none.
+    /// Fails with `AuthError::ReadOnlyStore` unless implemented.
+    async fn delete_role(&self, _app_id: ObjectId, _name: &str) -> Result<bool> {
+        Err(AuthError::ReadOnlyStore)
+    }
+}
+
+/// Fixed app and roles held in memory. Every role belongs to the app,
+/// whatever its `app` id.
+#[derive(Clone, Debug)]
+pub struct StaticStore {
+    app: App,
+    roles: Vec<Role>,
+}
+
+impl StaticStore {
+    pub fn new(app: App, roles: Vec<Role>) -> Self {
+        Self { app, roles }
+    }
+}
+
+#[async_trait]
+impl RoleStore for StaticStore {
+    async fn find_app(&self, name: &str) -> Result<Option<App>> {
+        Ok(Some(self.app.clone()).filter(|t| t.name == name))
+    }
+
+    async fn load_roles(&self, _app_id: ObjectId) -> Result<Vec<Role>> {
+        Ok(self.roles.clone())
+    }
+}
+
+/// Callback reporting the roles loaded so far out of the expected total.
+#[derive(Clone)]
+pub(crate) struct LoadProgress(pub(crate) Arc<dyn Fn(usize, Option<usize>) + Send + Sync>);
+
+impl std::fmt::Debug for LoadProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LoadProgress")
+    }
+}
+
+/// The apps and roles collections, the default store.
+#[derive(Clone, Debug)]
+pub(crate) struct MongoStore {
+    pub(crate) apps: Database,
+    pub(crate) roles: Database,
+    pub(crate) legacy_format: Option<LegacyFormat>,
+    pub(crate) load_progress: Option<LoadProgress>,
+}
+
+impl MongoStore {
+    fn decode_role(&self, mut src: Document) -> Result<Role> {
+        if let Some(t) = &self.legacy_format {
+            src = t.apply(src);
+        }
+
+        mongodb::bson::from_document(src).map_err(AuthError::InvalidRoleDocument)
+    }
+}
+
+#[async_trait]
+impl RoleStore for MongoStore {
+    async fn find_app(&self, name: &str) -> Result<Option<App>> {
+        self.apps
+            .collection::<App>(APPS)
+            .find_one(doc! { "name": name }, None)
+            .await
+            .map_err(AuthError::MongoFindOne)
+    }
+
+    async fn load_roles(&self, app_id: ObjectId) -> Result<Vec<Role>> {
+        let collection = self.roles.collection::<Document>(ROLES);
+        let filter = doc! {
+            "app": app_id
+        };
+
+        let total = match &self.load_progress {
+            Some(progress) => {
+                let total = collection
+                    .count_documents(filter.clone(), None)
+                    .await
+                    .map(|t| t as usize)
+                    .ok();
+                (progress.0)(0, total);
+                total
+            }
+            None => None,
+        };
+
+        let mut cursor = collection
+            .find(filter, None)
+            .await
+            .map_err(AuthError::MongoFind)?;
+
+        let mut roles = vec![];
+
+        while let Some(role) = cursor.try_next().await.map_err(read_cursor_error)? {
+            roles.push(self.decode_role(role)?);
+
+            if let Some(progress) = &self.load_progress {
+                (progress.0)(roles.len(), total);
+            }
+        }
+
+        Ok(roles)
+    }
+
+    async fn find_role(
+        &self,
+        app_id: ObjectId,
+        cache_key: CacheKey,
+        key: &str,
+        case_insensitive: bool,
+    ) -> Result<Option<Role>> {
+        let filter = match cache_key.filter(app_id, key) {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        let options = case_insensitive.then(|| {
+            FindOneOptions::builder()
+                .collation(
+                    Collation::builder()
+                        .locale("en")
+                        .strength(CollationStrength::Secondary)
+                        .build(),
+                )
+                .build()
+        });
+
+        self.roles
+            .collection::<Document>(ROLES)
+            .find_one(filter, options)
+            .await
+            .map_err(AuthError::MongoFindOne)?
+            .map(|t| self.decode_role(t))
+            .transpose()
+    }
+
+    /// Written in the current format, whatever the `legacy_format`. The
+    /// `createdAt` of a replaced role is kept.
+    async fn upsert_role(&self, app_id: ObjectId, role: &Role) -> Result<ObjectId> {
+        role.check_size()?;
+
+        let now = DateTime::now();
+
+        let mut set = mongodb::bson::to_document(role).map_err(AuthError::RoleSerialization)?;
+        // `Role` serializes its ids as strings for the API
+        set.remove("id");
+        set.remove("createdAt");
+        set.insert("app", app_id);
+        set.insert("updatedAt", now);
+
+        let filter = match role.id {
+            Some(id) => doc! { "_id": id, "app": app_id },
+            None => doc! { "app": app_id, "name": &role.name },
+        };
+
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .projection(doc! { "_id": 1 })
+            .build();
+
+        self.roles
+            .collection::<Document>(ROLES)
+            .find_one_and_update(
+                filter,
+                doc! { "$set": set, "$setOnInsert": { "createdAt": now } },
+                options,
+            )
+            .await
+            .map_err(AuthError::MongoFindOneAndUpdate)?
+            .and_then(|t| t.get_object_id("_id").ok())
+            .ok_or_else(|| AuthError::MissingRoles(vec![role.name.to_owned()]))
+    }
+
+    async fn delete_role(&self, app_id: ObjectId, name: &str) -> Result<bool> {
+        self.roles
+            .collection::<Document>(ROLES)
+            .delete_one(doc! { "app": app_id, "name": name }, None)
+            .await
+            .map(|t| t.deleted_count > 0)
+            .map_err(AuthError::MongoDeleteOne)
+    }
+}
+
+/// Roles cursor error. Oversized documents are reported as
+/// `AuthError::RoleTooLarge`, the driver doesn't tell which role it was.
+fn read_cursor_error(err: mongodb::error::Error) -> AuthError {
+    match err.kind.as_ref() {
+        ErrorKind::Command(t) if t.code == BSON_OBJECT_TOO_LARGE_CODE => {
+            AuthError::RoleTooLarge { name: None }
+        }
+        _ => AuthError::MongoReadCursor(err),
+    }
+}
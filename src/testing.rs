@@ -0,0 +1,45 @@
+//! Helpers for tests of code using `Auth`, enabled by the `testing` feature.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use mongodb::bson::DateTime;
+
+use crate::clock::Clock;
+
+/// Clock that only moves when told to, see `AuthBuilder::clock`. Its date
+/// starts at the current one and moves along.
+#[derive(Debug)]
+pub struct MockClock {
+    now: Mutex<Instant>,
+    started: (Instant, SystemTime),
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        let now = Instant::now();
+
+        Self {
+            now: Mutex::new(now),
+            started: (now, SystemTime::now()),
+        }
+    }
+}
+
+impl MockClock {
+    pub fn advance(&self, src: Duration) {
+        let mut lock = self.now.lock().unwrap_or_else(|t| t.into_inner());
+        *lock += src;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap_or_else(|t| t.into_inner())
+    }
+
+    fn wall_time(&self) -> DateTime {
+        let elapsed = self.now().saturating_duration_since(self.started.0);
+        DateTime::from_system_time(self.started.1 + elapsed)
+    }
+}